@@ -0,0 +1,58 @@
+//! Level lookups on a large generated level. Run with `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dungeon_crawl::dungeon::{Destination, EntityType, Level};
+use dungeon_crawl::generator::generate_level;
+use dungeon_crawl::position::Position;
+
+const SIZE: i32 = 256;
+
+fn find_entity(level: &Level, is_type: fn(&EntityType) -> bool) -> Position {
+    let entity = level
+        .entities()
+        .iter()
+        .find(|entity| is_type(&entity.entity_type))
+        .unwrap();
+    Position {
+        direction: entity.direction.clone(),
+        x: entity.x,
+        z: entity.z,
+    }
+}
+
+fn level_lookups(c: &mut Criterion) {
+    let stairs_down = Destination {
+        level: None,
+        position: None,
+    };
+    let level = generate_level(1, "Bench".to_owned(), 0, Some(stairs_down), SIZE, SIZE);
+
+    c.bench_function("get_tile on every cell", |b| {
+        b.iter(|| {
+            for z in 0..SIZE {
+                for x in 0..SIZE {
+                    black_box(level.get_tile(x, z));
+                }
+            }
+        })
+    });
+
+    c.bench_function("entities_at on every cell", |b| {
+        b.iter(|| {
+            for z in 0..SIZE {
+                for x in 0..SIZE {
+                    black_box(level.entities_at(x, z).count());
+                }
+            }
+        })
+    });
+
+    let from = find_entity(&level, |t| matches!(t, EntityType::PlayerStart));
+    let to = find_entity(&level, |t| matches!(t, EntityType::StairsDown));
+    c.bench_function("find_path to the stairs", |b| {
+        b.iter(|| black_box(level.find_path(&from, &to, |_, _| true)).unwrap())
+    });
+}
+
+criterion_group!(benches, level_lookups);
+criterion_main!(benches);
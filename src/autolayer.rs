@@ -0,0 +1,390 @@
+//! Auto-layer rules evaluated the way the LDtk editor does, so that grids
+//! edited outside of the editor still get their tiles. Perlin filters and
+//! pixel offsets are not supported.
+
+use crate::generator::Rng;
+use crate::ldtk::{AutoLayerRuleDefinition, AutoLayerRuleGroup, Checker, TileMode};
+
+/// Pattern value matching any non-empty cell. Negated, it matches empty cells.
+const ANYTHING: i64 = 1000001;
+
+/// The values of an IntGrid layer, row by row. 0 is an empty cell.
+pub struct IntGrid {
+    pub width: i32,
+    pub height: i32,
+    pub values: Vec<i64>,
+}
+impl IntGrid {
+    fn get(&self, x: i32, z: i32) -> Option<i64> {
+        if x < 0 || z < 0 || x >= self.width || z >= self.height {
+            return None;
+        }
+        self.values.get((z * self.width + x) as usize).copied()
+    }
+}
+
+/// A tile placed by a rule. `flip` has the bits of LDtk's tile `f`: 1 for
+/// horizontal, 2 for vertical.
+pub struct AutoTile {
+    pub x: i32,
+    pub z: i32,
+    pub tile_id: i64,
+    pub flip: i64,
+}
+
+/// Runs the active rules over `grid` in order. A rule that breaks on match
+/// keeps the later ones off the cells it matched. `optional_rules` are the
+/// optional groups enabled on the layer instance.
+pub fn apply_rules(
+    rule_groups: &[AutoLayerRuleGroup],
+    optional_rules: &[i64],
+    grid: &IntGrid,
+    tileset_columns: i64,
+    seed: i64,
+) -> Vec<AutoTile> {
+    let mut tiles = vec![];
+    let mut done = vec![false; grid.values.len()];
+    let rules = rule_groups
+        .iter()
+        .filter(|group| group.active && (!group.is_optional || optional_rules.contains(&group.uid)))
+        .flat_map(|group| group.rules.iter())
+        .filter(|rule| rule.active && !rule.tile_ids.is_empty());
+    for rule in rules {
+        for z in 0..grid.height {
+            for x in 0..grid.width {
+                let index = (z * grid.width + x) as usize;
+                if done[index] || !in_modulo(rule, x, z) {
+                    continue;
+                }
+                let mut rng = Rng((seed as u64) ^ (rule.uid as u64).rotate_left(32) ^ index as u64);
+                if rule.chance < 1.0 && rng.range(0, 10000) as f64 >= rule.chance * 10000.0 {
+                    continue;
+                }
+                let Some(flip) = get_matching_flip(rule, grid, x, z) else {
+                    continue;
+                };
+                place_tiles(&mut tiles, rule, &mut rng, tileset_columns, x, z, flip);
+                if rule.break_on_match {
+                    done[index] = true;
+                }
+            }
+        }
+    }
+    tiles
+}
+
+/// Whether the cell is on the rule's modulo grid, shifted every other row or
+/// column in checker mode.
+fn in_modulo(rule: &AutoLayerRuleDefinition, x: i32, z: i32) -> bool {
+    let x = x as i64 - rule.x_offset;
+    let z = z as i64 - rule.y_offset;
+    let x_modulo = rule.x_modulo.max(1);
+    let y_modulo = rule.y_modulo.max(1);
+    let column = match rule.checker {
+        Checker::Horizontal => x + z.div_euclid(y_modulo).rem_euclid(2),
+        _ => x,
+    };
+    let row = match rule.checker {
+        Checker::Vertical => z + x.div_euclid(x_modulo).rem_euclid(2),
+        _ => z,
+    };
+    column.rem_euclid(x_modulo) == 0 && row.rem_euclid(y_modulo) == 0
+}
+
+/// The first orientation of the pattern that matches around the cell.
+fn get_matching_flip(
+    rule: &AutoLayerRuleDefinition,
+    grid: &IntGrid,
+    x: i32,
+    z: i32,
+) -> Option<i64> {
+    let mut flips = vec![0];
+    if rule.flip_x {
+        flips.push(1);
+    }
+    if rule.flip_y {
+        flips.push(2);
+    }
+    if rule.flip_x && rule.flip_y {
+        flips.push(3);
+    }
+    flips
+        .into_iter()
+        .find(|flip| matches_pattern(rule, grid, x, z, *flip))
+}
+
+fn matches_pattern(
+    rule: &AutoLayerRuleDefinition,
+    grid: &IntGrid,
+    x: i32,
+    z: i32,
+    flip: i64,
+) -> bool {
+    let size = rule.size as i32;
+    let center = size / 2;
+    let (sign_x, sign_z) = (
+        if flip & 1 != 0 { -1 } else { 1 },
+        if flip & 2 != 0 { -1 } else { 1 },
+    );
+    rule.pattern.iter().enumerate().all(|(index, expected)| {
+        if *expected == 0 {
+            return true;
+        }
+        let dx = index as i32 % size - center;
+        let dz = index as i32 / size - center;
+        let Some(value) = grid
+            .get(x + dx * sign_x, z + dz * sign_z)
+            .or(rule.out_of_bounds_value)
+        else {
+            return false;
+        };
+        match *expected {
+            ANYTHING => value != 0,
+            expected if expected == -ANYTHING => value == 0,
+            expected if expected > 0 => value == expected,
+            expected => value != -expected,
+        }
+    })
+}
+
+/// Places one random tile of the rule, or its whole stamp around the pivot.
+fn place_tiles(
+    tiles: &mut Vec<AutoTile>,
+    rule: &AutoLayerRuleDefinition,
+    rng: &mut Rng,
+    tileset_columns: i64,
+    x: i32,
+    z: i32,
+    flip: i64,
+) {
+    match rule.tile_mode {
+        TileMode::Single => {
+            let tile_id = rule.tile_ids[rng.range(0, rule.tile_ids.len() as i32) as usize];
+            tiles.push(AutoTile {
+                x,
+                z,
+                tile_id,
+                flip,
+            });
+        }
+        TileMode::Stamp => {
+            let columns = tileset_columns.max(1);
+            let cells = rule.tile_ids.iter().map(|id| (id % columns, id / columns));
+            let left = cells.clone().map(|(column, _)| column).min().unwrap_or(0);
+            let right = cells.clone().map(|(column, _)| column).max().unwrap_or(0);
+            let top = cells.clone().map(|(_, row)| row).min().unwrap_or(0);
+            let bottom = cells.clone().map(|(_, row)| row).max().unwrap_or(0);
+            let pivot_x = ((right - left) as f64 * rule.pivot_x) as i64;
+            let pivot_z = ((bottom - top) as f64 * rule.pivot_y) as i64;
+            for (tile_id, (column, row)) in rule.tile_ids.iter().zip(cells) {
+                // a flipped stamp is mirrored as a whole
+                let dx = if flip & 1 != 0 {
+                    right - column
+                } else {
+                    column - left
+                } - pivot_x;
+                let dz = if flip & 2 != 0 {
+                    bottom - row
+                } else {
+                    row - top
+                } - pivot_z;
+                tiles.push(AutoTile {
+                    x: x + dx as i32,
+                    z: z + dz as i32,
+                    tile_id: *tile_id,
+                    flip,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ldtk::AutoLayerRuleGroup;
+
+    /// A grid from rows of digits, with `.` for an empty cell.
+    fn grid(rows: &[&str]) -> IntGrid {
+        IntGrid {
+            width: rows[0].len() as i32,
+            height: rows.len() as i32,
+            values: rows
+                .iter()
+                .flat_map(|row| row.chars())
+                .map(|c| c.to_digit(10).unwrap_or(0) as i64)
+                .collect(),
+        }
+    }
+
+    /// A rule placing tile 0 wherever the square `pattern` matches.
+    fn rule(pattern: &[i64]) -> AutoLayerRuleDefinition {
+        AutoLayerRuleDefinition {
+            active: true,
+            alpha: 1.0,
+            break_on_match: false,
+            chance: 1.0,
+            checker: Checker::None,
+            flip_x: false,
+            flip_y: false,
+            out_of_bounds_value: None,
+            pattern: pattern.to_vec(),
+            perlin_active: false,
+            perlin_octaves: 2.0,
+            perlin_scale: 0.2,
+            perlin_seed: 0.0,
+            pivot_x: 0.0,
+            pivot_y: 0.0,
+            size: (pattern.len() as f64).sqrt() as i64,
+            tile_ids: vec![0],
+            tile_mode: TileMode::Single,
+            tile_random_x_max: 0,
+            tile_random_x_min: 0,
+            tile_random_y_max: 0,
+            tile_random_y_min: 0,
+            tile_x_offset: 0,
+            tile_y_offset: 0,
+            uid: 0,
+            x_modulo: 1,
+            x_offset: 0,
+            y_modulo: 1,
+            y_offset: 0,
+        }
+    }
+
+    /// Placed tiles as `(x, z, tile_id, flip)`, with a tileset 4 tiles wide.
+    fn apply(rules: Vec<AutoLayerRuleDefinition>, grid: &IntGrid) -> Vec<(i32, i32, i64, i64)> {
+        let group = AutoLayerRuleGroup {
+            active: true,
+            collapsed: None,
+            is_optional: false,
+            name: "Test".to_owned(),
+            rules,
+            uid: 0,
+            uses_wizard: false,
+        };
+        apply_rules(&[group], &[], grid, 4, 0)
+            .into_iter()
+            .map(|tile| (tile.x, tile.z, tile.tile_id, tile.flip))
+            .collect()
+    }
+
+    fn cells(tiles: &[(i32, i32, i64, i64)]) -> Vec<(i32, i32)> {
+        tiles.iter().map(|&(x, z, _, _)| (x, z)).collect()
+    }
+
+    #[test]
+    fn modulo_with_offsets() {
+        let mut rule = rule(&[ANYTHING]);
+        rule.x_modulo = 2;
+        rule.x_offset = 1;
+        rule.y_modulo = 2;
+        rule.y_offset = 1;
+        let tiles = apply(vec![rule], &grid(&["1111", "1111", "1111"]));
+        assert_eq!(cells(&tiles), vec![(1, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn checker_keeps_offsets() {
+        let mut horizontal = rule(&[ANYTHING]);
+        horizontal.checker = Checker::Horizontal;
+        horizontal.x_modulo = 2;
+        horizontal.x_offset = 1;
+        let tiles = apply(vec![horizontal], &grid(&["1111", "1111"]));
+        // every other row is shifted by one column
+        assert_eq!(cells(&tiles), vec![(1, 0), (3, 0), (0, 1), (2, 1)]);
+
+        let mut vertical = rule(&[ANYTHING]);
+        vertical.checker = Checker::Vertical;
+        vertical.y_modulo = 2;
+        vertical.y_offset = 1;
+        let tiles = apply(vec![vertical], &grid(&["11", "11", "11"]));
+        assert_eq!(cells(&tiles), vec![(1, 0), (0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn flips_mirror_the_pattern() {
+        // a cell with a wall on its left
+        let pattern = [0, 0, 0, 1, 1, 0, 0, 0, 0];
+        let level = grid(&["11."]);
+        assert_eq!(apply(vec![rule(&pattern)], &level), vec![(1, 0, 0, 0)]);
+
+        let mut flipped = rule(&pattern);
+        flipped.flip_x = true;
+        assert_eq!(
+            apply(vec![flipped], &level),
+            vec![(0, 0, 0, 1), (1, 0, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn break_on_match_keeps_later_rules_off() {
+        let mut first = rule(&[2]);
+        first.tile_ids = vec![1];
+        let mut second = rule(&[ANYTHING]);
+        second.tile_ids = vec![2];
+        let level = grid(&["12"]);
+        assert_eq!(
+            apply(vec![first, second], &level),
+            vec![(1, 0, 1, 0), (0, 0, 2, 0), (1, 0, 2, 0)]
+        );
+
+        let mut first = rule(&[2]);
+        first.tile_ids = vec![1];
+        first.break_on_match = true;
+        let mut second = rule(&[ANYTHING]);
+        second.tile_ids = vec![2];
+        assert_eq!(
+            apply(vec![first, second], &level),
+            vec![(1, 0, 1, 0), (0, 0, 2, 0)]
+        );
+    }
+
+    #[test]
+    fn stamp_places_every_tile() {
+        // tiles 1, 2 and 5 of a tileset 4 wide, pivoted on the top left
+        let mut stamp = rule(&[1]);
+        stamp.tile_ids = vec![1, 2, 5];
+        stamp.tile_mode = TileMode::Stamp;
+        assert_eq!(
+            apply(vec![stamp], &grid(&["1"])),
+            vec![(0, 0, 1, 0), (1, 0, 2, 0), (0, 1, 5, 0)]
+        );
+
+        let mut single = rule(&[1]);
+        single.tile_ids = vec![1, 2, 5];
+        let tiles = apply(vec![single], &grid(&["1"]));
+        assert_eq!(cells(&tiles), vec![(0, 0)]);
+        assert!([1, 2, 5].contains(&tiles[0].2));
+    }
+
+    #[test]
+    fn out_of_bounds_value() {
+        // a cell with a wall above it
+        let pattern = [0, 1, 0, 0, 1, 0, 0, 0, 0];
+        let level = grid(&["1", "1"]);
+        assert_eq!(cells(&apply(vec![rule(&pattern)], &level)), vec![(0, 1)]);
+
+        let mut walled = rule(&pattern);
+        walled.out_of_bounds_value = Some(1);
+        assert_eq!(cells(&apply(vec![walled], &level)), vec![(0, 0), (0, 1)]);
+    }
+
+    #[test]
+    fn anything_matches_any_value() {
+        let level = grid(&["12.3"]);
+        assert_eq!(
+            cells(&apply(vec![rule(&[ANYTHING])], &level)),
+            vec![(0, 0), (1, 0), (3, 0)]
+        );
+        assert_eq!(
+            cells(&apply(vec![rule(&[-ANYTHING])], &level)),
+            vec![(2, 0)]
+        );
+        // a negative value matches anything else, empty cells included
+        assert_eq!(
+            cells(&apply(vec![rule(&[-2])], &level)),
+            vec![(0, 0), (2, 0), (3, 0)]
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use crate::cat::Cat;
+use crate::dungeon::{Dungeon, DungeonLevel, Level};
+use crate::input::{Action, ActionEvent};
+use crate::player::Player;
+use crate::position::{Direction, Position};
+use crate::travel::{Travel, TravelGoal};
+use crate::world::WorldState;
+use ::serde::{Deserialize, Serialize};
+use bevy::prelude::*;
+use bevy::ui::RelativeCursorPosition;
+use std::collections::{HashMap, HashSet};
+
+const SIGHT_DISTANCE: i32 = 8;
+const MINIMAP_CELL: f32 = 6.0;
+const MINIMAP_RADIUS: i32 = 4;
+const FLOOR_COLOR: Color = Color::rgba(0.3, 0.3, 0.35, 0.8);
+const WALL_COLOR: Color = Color::WHITE;
+const PLAYER_COLOR: Color = Color::rgb(0.35, 0.75, 0.35);
+const CAT_COLOR: Color = Color::rgb(1.0, 0.6, 0.2);
+const MESSAGE_COLOR: Color = Color::rgb(1.0, 1.0, 0.4);
+
+/// Tiles the player has seen, keyed by level index.
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
+pub struct Explored(pub HashMap<usize, HashSet<(i32, i32)>>);
+
+#[derive(Component)]
+pub struct Minimap;
+
+#[derive(Component)]
+pub struct FullMap;
+
+/// Marks the tiles visible from `position`: straight ahead until a wall or a
+/// closed door, plus the open side tiles along the way.
+pub fn get_visible_tiles(
+    level: &Level,
+    level_index: usize,
+    world_state: &WorldState,
+    position: &Position,
+) -> Vec<(i32, i32)> {
+    let is_open = |x: i32, z: i32, direction: &Direction| {
+        level
+            .get_tile(x, z)
+            .map(|tile| !tile.has_wall(direction))
+            .unwrap_or(false)
+            && world_state.is_passable(level, level_index, x, z, direction)
+    };
+
+    let mut visible = vec![(position.x, position.z)];
+    let mut sight = position.clone();
+    for _ in 0..SIGHT_DISTANCE {
+        for side in [sight.direction.clone(), sight.direction.reverse()] {
+            let mut rotated = sight.clone();
+            rotated.direction = side;
+            rotated.rotate_right();
+            if is_open(sight.x, sight.z, &rotated.direction) {
+                rotated.go_forward();
+                visible.push((rotated.x, rotated.z));
+            }
+        }
+        if !is_open(sight.x, sight.z, &sight.direction) {
+            break;
+        }
+        sight.go_forward();
+        visible.push((sight.x, sight.z));
+    }
+    visible
+}
+
+pub fn update_explored(
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    world_state: Res<WorldState>,
+    mut explored: ResMut<Explored>,
+    query: Query<&Position, (With<Player>, Changed<Position>)>,
+) {
+    let Ok(position) = query.get_single() else {
+        return;
+    };
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    let visible = get_visible_tiles(level, dungeon_level.0, &world_state, position);
+    let is_new = |tile: &(i32, i32)| {
+        explored
+            .0
+            .get(&dungeon_level.0)
+            .map(|tiles| !tiles.contains(tile))
+            .unwrap_or(true)
+    };
+    if visible.iter().any(is_new) {
+        explored
+            .0
+            .entry(dungeon_level.0)
+            .or_default()
+            .extend(visible);
+    }
+}
+
+pub fn setup_automap(mut commands: Commands) {
+    let size = MINIMAP_CELL * (MINIMAP_RADIUS * 2 + 1) as f32;
+    commands.spawn((
+        Minimap,
+        Interaction::default(),
+        RelativeCursorPosition::default(),
+        NodeBundle {
+            z_index: ZIndex::Global(110),
+            style: Style {
+                position_type: PositionType::Absolute,
+                right: Val::Px(4.0),
+                bottom: Val::Px(4.0),
+                width: Val::Px(size),
+                height: Val::Px(size),
+                overflow: Overflow::clip(),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+            ..default()
+        },
+    ));
+    commands.spawn((
+        FullMap,
+        Interaction::default(),
+        RelativeCursorPosition::default(),
+        NodeBundle {
+            z_index: ZIndex::Global(120),
+            style: Style {
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.85).into(),
+            ..default()
+        },
+    ));
+}
+
+pub fn toggle_full_map(
+    mut action_events: EventReader<ActionEvent>,
+    mut query: Query<&mut Style, With<FullMap>>,
+) {
+    if !action_events.iter().any(|event| event.0 == Action::Map) {
+        return;
+    }
+    let mut style = query.single_mut();
+    style.display = if style.display == Display::None {
+        Display::Flex
+    } else {
+        Display::None
+    };
+}
+
+/// Cells from (`min_x`, `min_z`) are drawn at the node's origin with `cell` pixels each.
+#[derive(Component)]
+pub struct MapView {
+    min_x: i32,
+    min_z: i32,
+    max_x: i32,
+    max_z: i32,
+    cell: f32,
+}
+
+fn draw_map(
+    parent: &mut ChildBuilder,
+    view: &MapView,
+    level: &Level,
+    explored: &HashSet<(i32, i32)>,
+    cats: &[(i32, i32)],
+    player: &Position,
+) {
+    let cell_style = |x: i32, z: i32, inset: f32| Style {
+        position_type: PositionType::Absolute,
+        left: Val::Px((x - view.min_x) as f32 * view.cell + inset),
+        top: Val::Px((z - view.min_z) as f32 * view.cell + inset),
+        width: Val::Px(view.cell - inset * 2.0),
+        height: Val::Px(view.cell - inset * 2.0),
+        ..default()
+    };
+    let in_view =
+        |x: i32, z: i32| x >= view.min_x && x <= view.max_x && z >= view.min_z && z <= view.max_z;
+
+    let tiles = (view.min_z..=view.max_z)
+        .flat_map(|z| (view.min_x..=view.max_x).map(move |x| (x, z)))
+        .filter(|cell| explored.contains(cell))
+        .filter_map(|(x, z)| level.get_tile(x, z));
+    for tile in tiles {
+        let wall = |direction: Direction| {
+            if tile.has_wall(&direction) {
+                Val::Px(1.0)
+            } else {
+                Val::Px(0.0)
+            }
+        };
+        parent.spawn(NodeBundle {
+            style: Style {
+                border: UiRect {
+                    left: wall(Direction::Left),
+                    right: wall(Direction::Right),
+                    top: wall(Direction::Up),
+                    bottom: wall(Direction::Down),
+                },
+                ..cell_style(tile.x, tile.z, 0.0)
+            },
+            background_color: FLOOR_COLOR.into(),
+            border_color: WALL_COLOR.into(),
+            ..default()
+        });
+    }
+
+    for entity in level
+        .entities()
+        .iter()
+        .filter(|entity| in_view(entity.x, entity.z) && explored.contains(&(entity.x, entity.z)))
+    {
+        if entity.message.is_none() || entity.is_actor() {
+            continue;
+        }
+        parent.spawn(NodeBundle {
+            style: cell_style(entity.x, entity.z, view.cell * 0.25),
+            background_color: MESSAGE_COLOR.into(),
+            ..default()
+        });
+    }
+
+    for &(x, z) in cats
+        .iter()
+        .filter(|(x, z)| in_view(*x, *z) && explored.contains(&(*x, *z)))
+    {
+        parent.spawn(NodeBundle {
+            style: cell_style(x, z, view.cell * 0.25),
+            background_color: CAT_COLOR.into(),
+            ..default()
+        });
+    }
+
+    if in_view(player.x, player.z) {
+        // the facing is shown as a bar on the side the player looks at
+        let thickness = (view.cell * 0.25).max(1.0);
+        let mut style = cell_style(player.x, player.z, view.cell * 0.2);
+        parent
+            .spawn(NodeBundle {
+                style: style.clone(),
+                background_color: PLAYER_COLOR.into(),
+                ..default()
+            })
+            .with_children(|parent| {
+                let side = view.cell * 0.6 - thickness;
+                style.left = Val::Px(0.0);
+                style.top = Val::Px(0.0);
+                match player.direction {
+                    Direction::Up => style.height = Val::Px(thickness),
+                    Direction::Down => {
+                        style.top = Val::Px(side);
+                        style.height = Val::Px(thickness);
+                    }
+                    Direction::Left => style.width = Val::Px(thickness),
+                    Direction::Right => {
+                        style.left = Val::Px(side);
+                        style.width = Val::Px(thickness);
+                    }
+                }
+                parent.spawn(NodeBundle {
+                    style,
+                    background_color: WALL_COLOR.into(),
+                    ..default()
+                });
+            });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_automap(
+    mut commands: Commands,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    explored: Res<Explored>,
+    windows: Query<&Window>,
+    player_query: Query<Ref<Position>, With<Player>>,
+    cat_query: Query<Ref<Position>, (With<Cat>, Without<Player>)>,
+    minimap_query: Query<Entity, With<Minimap>>,
+    full_map_query: Query<(Entity, Ref<Style>), With<FullMap>>,
+) {
+    let Ok(position) = player_query.get_single() else {
+        return;
+    };
+    let (full_map, full_map_style) = full_map_query.single();
+    if !explored.is_changed()
+        && !position.is_changed()
+        && !full_map_style.is_changed()
+        && !cat_query.iter().any(|cat| cat.is_changed())
+    {
+        return;
+    }
+    let cats: Vec<(i32, i32)> = cat_query.iter().map(|cat| (cat.x, cat.z)).collect();
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    let empty = HashSet::new();
+    let tiles = explored.0.get(&dungeon_level.0).unwrap_or(&empty);
+
+    let minimap = minimap_query.single();
+    let view = MapView {
+        min_x: position.x - MINIMAP_RADIUS,
+        min_z: position.z - MINIMAP_RADIUS,
+        max_x: position.x + MINIMAP_RADIUS,
+        max_z: position.z + MINIMAP_RADIUS,
+        cell: MINIMAP_CELL,
+    };
+    commands
+        .entity(minimap)
+        .despawn_descendants()
+        .with_children(|parent| draw_map(parent, &view, level, tiles, &cats, &position))
+        .insert(view);
+
+    commands.entity(full_map).despawn_descendants();
+    if full_map_style.display == Display::None {
+        return;
+    }
+    let window = windows.single();
+    // levels wider than the window still get a pixel per cell
+    let cell = (window.width() / level.width as f32)
+        .min(window.height() / level.length as f32)
+        .floor()
+        .max(1.0);
+    let view = MapView {
+        min_x: 0,
+        min_z: 0,
+        max_x: level.width - 1,
+        max_z: level.length - 1,
+        cell,
+    };
+    commands
+        .entity(full_map)
+        .with_children(|parent| draw_map(parent, &view, level, tiles, &cats, &position))
+        .insert(view);
+}
+
+/// Clicking an explored tile on the minimap or the full map walks the player
+/// there. The full map closes so that the walk can be watched.
+#[allow(clippy::type_complexity)]
+pub fn click_automap(
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    explored: Res<Explored>,
+    mut map_query: Query<
+        (
+            &Interaction,
+            &RelativeCursorPosition,
+            &Node,
+            &MapView,
+            &mut Style,
+            Option<&FullMap>,
+        ),
+        Changed<Interaction>,
+    >,
+    mut travel_query: Query<&mut Travel, With<Player>>,
+) {
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    for (interaction, cursor, node, view, mut style, full_map) in &mut map_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let Some(cursor) = cursor.normalized else {
+            continue;
+        };
+        let cell = (cursor * node.size() / view.cell).floor();
+        let (x, z) = (view.min_x + cell.x as i32, view.min_z + cell.y as i32);
+        let is_explored = explored
+            .0
+            .get(&dungeon_level.0)
+            .is_some_and(|tiles| tiles.contains(&(x, z)));
+        if !is_explored || level.get_tile(x, z).is_none() {
+            continue;
+        }
+        if let Ok(mut travel) = travel_query.get_single_mut() {
+            travel.start(TravelGoal::Tile(x, z));
+        }
+        if full_map.is_some() {
+            style.display = Display::None;
+        }
+    }
+}
@@ -1,11 +1,43 @@
-use crate::dungeon::{Dungeon, DungeonLevel, EntityType};
-use crate::position::{get_transform, Direction};
-use crate::SpawnDungeonEvent;
+use crate::combat::Enemy;
+use crate::dialogue::DialogueSource;
+use crate::dungeon::{Dungeon, DungeonLevel, EntityType, Terrain};
+use crate::generator::Rng;
+use crate::player::Player;
+use crate::position::{get_transform, Direction, Position};
+use crate::world::{EntityIid, WorldState};
+use crate::{SpawnDungeonEvent, TurnEvent};
 use bevy::prelude::*;
+use bevy_tweening::{lens::*, *};
+use std::collections::HashSet;
 use std::f32::consts::PI;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum CatBehaviour {
+    #[default]
+    Wander,
+    Follow,
+    Flee,
+}
+impl FromStr for CatBehaviour {
+    type Err = ();
+    fn from_str(input: &str) -> Result<CatBehaviour, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "wander" => Ok(CatBehaviour::Wander),
+            "follow" => Ok(CatBehaviour::Follow),
+            "flee" => Ok(CatBehaviour::Flee),
+            _ => Err(()),
+        }
+    }
+}
 
 #[derive(Component)]
-pub struct Cat;
+pub struct Cat {
+    pub behaviour: CatBehaviour,
+    pub message: Option<String>,
+    pub dialogue: Option<DialogueSource>,
+}
 
 #[derive(Resource)]
 pub struct CatAnimation(pub Handle<AnimationClip>);
@@ -22,6 +54,7 @@ pub fn spawn_cats(
     asset_server: ResMut<AssetServer>,
     dungeon: Res<Dungeon>,
     dungeon_level: Res<DungeonLevel>,
+    world_state: Res<WorldState>,
     spawn_events: EventReader<SpawnDungeonEvent>,
 ) {
     if spawn_events.is_empty() {
@@ -30,29 +63,36 @@ pub fn spawn_cats(
     let level = dungeon.levels.get(dungeon_level.0).unwrap();
     let scene_cat = asset_server.load("cat.glb#Scene0");
 
-    let spawn_cat = |commands: &mut Commands, direction: &Direction, x: f32, z: f32| {
-        commands.spawn((
-            Cat,
-            SceneBundle {
-                scene: scene_cat.clone(),
-                transform: get_cat_transform(direction, x, z),
-                ..default()
-            },
-        ));
-    };
-
-    for entity in level.entities.iter() {
-        match entity.entity_type {
-            EntityType::Cat => {
-                spawn_cat(
-                    &mut commands,
-                    &entity.direction,
-                    entity.x as f32,
-                    entity.z as f32,
-                );
-            }
-            _ => (),
-        };
+    for entity in level.entities().iter() {
+        if let EntityType::Cat = entity.entity_type {
+            let position = world_state.position(
+                dungeon_level.0,
+                &entity.iid,
+                Position {
+                    direction: entity.direction.clone(),
+                    x: entity.x,
+                    z: entity.z,
+                },
+            );
+            commands.spawn((
+                Cat {
+                    behaviour: entity.behaviour.unwrap_or_default(),
+                    message: entity.message.clone(),
+                    dialogue: entity.dialogue.clone(),
+                },
+                EntityIid(entity.iid.clone()),
+                SceneBundle {
+                    scene: scene_cat.clone(),
+                    transform: get_cat_transform(
+                        &position.direction,
+                        position.x as f32,
+                        position.z as f32,
+                    ),
+                    ..default()
+                },
+                position,
+            ));
+        }
     }
 }
 
@@ -61,7 +101,7 @@ pub fn check_descendant<T: Component>(
     parents: &Query<&Parent>,
     entity: Entity,
 ) -> bool {
-    if components.contains(entity.clone()) {
+    if components.contains(entity) {
         return true;
     }
     if let Ok(parent) = parents.get(entity) {
@@ -84,6 +124,122 @@ pub fn setup_cats_animation(
     }
 }
 
+fn distance(a: &Position, b: &Position) -> i32 {
+    (a.x - b.x).abs() + (a.z - b.z).abs()
+}
+
+fn get_cat_move_animator(transform: &Transform, position: &Position) -> Animator<Transform> {
+    let new_transform =
+        get_cat_transform(&position.direction, position.x as f32, position.z as f32);
+    Animator::<Transform>::new(Tracks::new(vec![
+        Tween::new(
+            EaseFunction::QuadraticOut,
+            Duration::from_millis(200),
+            TransformRotationLens {
+                start: transform.rotation,
+                end: new_transform.rotation,
+            },
+        ),
+        Tween::new(
+            EaseFunction::QuadraticOut,
+            Duration::from_millis(200),
+            TransformPositionLens {
+                start: transform.translation,
+                end: new_transform.translation,
+            },
+        ),
+    ]))
+}
+
+/// Every cat takes one step after each player turn. Cats never enter the
+/// player's tile or one taken by another cat or an enemy. Where they end up
+/// is kept in `WorldState`.
+#[allow(clippy::too_many_arguments)]
+pub fn update_cats(
+    mut commands: Commands,
+    mut turn_events: EventReader<TurnEvent>,
+    time: Res<Time>,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    mut world_state: ResMut<WorldState>,
+    mut rng: Local<Option<Rng>>,
+    player_query: Query<&Position, With<Player>>,
+    enemy_query: Query<&Position, (With<Enemy>, Without<Cat>)>,
+    mut cat_query: Query<(Entity, &Cat, &EntityIid, &Transform, &mut Position), Without<Player>>,
+) {
+    if turn_events.iter().count() == 0 {
+        return;
+    }
+    let Ok(player) = player_query.get_single() else {
+        return;
+    };
+    let rng = rng.get_or_insert_with(|| Rng(time.raw_elapsed().as_nanos() as u64));
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+
+    let mut occupied: HashSet<(i32, i32)> = cat_query
+        .iter()
+        .map(|(_, _, _, _, position)| (position.x, position.z))
+        .collect();
+    occupied.insert((player.x, player.z));
+    occupied.extend(enemy_query.iter().map(|enemy| (enemy.x, enemy.z)));
+
+    for (entity, cat, iid, transform, mut position) in &mut cat_query {
+        let is_passable = |from: &Position, direction: &Direction| {
+            world_state.is_passable(level, dungeon_level.0, from.x, from.z, direction)
+        };
+        let steps: Vec<Position> = level
+            .neighbours(&position)
+            .into_iter()
+            .filter(|next| is_passable(&position, &next.direction))
+            .filter(|next| {
+                // cats keep to dry, safe floors
+                matches!(
+                    world_state.terrain(level, dungeon_level.0, next.x, next.z),
+                    Some(Terrain::Floor | Terrain::Ice)
+                ) && !occupied.contains(&(next.x, next.z))
+            })
+            .collect();
+
+        let now = distance(&position, player);
+        let next = match cat.behaviour {
+            // wandering cats rest every other turn on average
+            CatBehaviour::Wander if !steps.is_empty() && rng.range(0, 2) == 0 => {
+                let index = rng.range(0, steps.len() as i32) as usize;
+                steps.into_iter().nth(index)
+            }
+            CatBehaviour::Wander => None,
+            // following cats walk around walls, and wait when the way is taken
+            CatBehaviour::Follow if now > 1 => level
+                .find_path(&position, player, is_passable)
+                .and_then(|path| path.into_iter().next())
+                .filter(|next| {
+                    steps
+                        .iter()
+                        .any(|step| step.x == next.x && step.z == next.z)
+                }),
+            CatBehaviour::Follow => None,
+            CatBehaviour::Flee => steps
+                .into_iter()
+                .filter(|next| distance(next, player) > now)
+                .max_by_key(|next| distance(next, player)),
+        };
+        let Some(next) = next else {
+            continue;
+        };
+
+        occupied.remove(&(position.x, position.z));
+        occupied.insert((next.x, next.z));
+        commands
+            .entity(entity)
+            .insert(get_cat_move_animator(transform, &next));
+        world_state
+            .level_mut(dungeon_level.0)
+            .positions
+            .insert(iid.0.clone(), next.clone());
+        *position = next;
+    }
+}
+
 fn get_cat_transform(direction: &Direction, x: f32, z: f32) -> Transform {
     let mut transform = get_transform(direction, x, z);
     transform.rotate_y(PI);
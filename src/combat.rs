@@ -0,0 +1,430 @@
+use crate::dungeon::{Dungeon, DungeonLevel, EntityType};
+use crate::generator::Rng;
+use crate::input::{Action, ActionEvent};
+use crate::inventory::Inventory;
+use crate::player::{get_cannot_move_animator, Player};
+use crate::position::Position;
+use crate::stats::PlayerStats;
+use crate::world::{EntityIid, WorldState};
+use crate::{MessageEvent, SpawnDungeonEvent, UiFont, NORMAL_BUTTON};
+use ::serde::{Deserialize, Serialize};
+use bevy::prelude::*;
+
+const SELECTED_COLOR: Color = Color::rgb(1.0, 1.0, 0.4);
+const ENEMY_DAMAGE_COLOR: Color = Color::WHITE;
+const PLAYER_DAMAGE_COLOR: Color = Color::rgb(1.0, 0.3, 0.3);
+const DAMAGE_NUMBER_SECONDS: f32 = 0.8;
+/// Where damage numbers start, in percent from the top: the enemy stands in
+/// the middle of the view, the player's damage shows lower.
+const ENEMY_DAMAGE_TOP: f32 = 35.0;
+const PLAYER_DAMAGE_TOP: f32 = 60.0;
+
+pub const ENEMY_STATS: Stats = Stats::new(10, 3, 1);
+
+const FIGHT_COMMANDS: [FightCommand; 3] = [
+    FightCommand::Attack,
+    FightCommand::Defend,
+    FightCommand::Flee,
+];
+
+#[derive(Component, Clone, Copy, Serialize, Deserialize)]
+pub struct Stats {
+    pub hp: i32,
+    pub max_hp: i32,
+    pub attack: i32,
+    pub defense: i32,
+}
+impl Stats {
+    pub const fn new(hp: i32, attack: i32, defense: i32) -> Self {
+        Stats {
+            hp,
+            max_hp: hp,
+            attack,
+            defense,
+        }
+    }
+
+    /// Damage dealt to `target`, at least 1 before the random bonus.
+    fn damage_to(&self, target: &Stats, rng: &mut Rng) -> i32 {
+        (self.attack - target.defense).max(1) + rng.range(0, 2)
+    }
+}
+
+#[derive(Component)]
+pub struct Enemy;
+
+/// Starts a fight with the enemy entity.
+#[derive(Event)]
+pub struct FightEvent(pub Entity);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FightCommand {
+    Attack,
+    Defend,
+    Flee,
+}
+impl FightCommand {
+    fn label(&self) -> &'static str {
+        match self {
+            FightCommand::Attack => "Attack",
+            FightCommand::Defend => "Defend",
+            FightCommand::Flee => "Flee",
+        }
+    }
+}
+
+pub struct Fight {
+    pub enemy: Entity,
+    selected: usize,
+}
+
+/// The fight in progress. Player moves and door interaction wait while it is set.
+#[derive(Resource, Default)]
+pub struct ActiveFight(pub Option<Fight>);
+
+#[derive(Component)]
+pub struct FightPanel;
+
+#[derive(Component)]
+pub struct FightText;
+
+#[derive(Component)]
+pub struct FightCommandButton(pub FightCommand);
+
+#[derive(Component)]
+pub struct FightCommandText(pub FightCommand);
+
+/// Floats up from where it was spawned and disappears.
+#[derive(Component)]
+pub struct DamageNumber(Timer);
+
+pub fn spawn_enemies(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    world_state: Res<WorldState>,
+    spawn_events: EventReader<SpawnDungeonEvent>,
+) {
+    if spawn_events.is_empty() {
+        return;
+    }
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    let mesh = meshes.add(
+        shape::Capsule {
+            radius: 0.15,
+            depth: 0.3,
+            ..default()
+        }
+        .into(),
+    );
+    let material = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.7, 0.15, 0.15),
+        unlit: false,
+        ..default()
+    });
+
+    for entity in level.entities().iter() {
+        if !matches!(entity.entity_type, EntityType::Enemy)
+            || world_state.is_removed(dungeon_level.0, &entity.iid)
+        {
+            continue;
+        }
+        commands.spawn((
+            Enemy,
+            EntityIid(entity.iid.clone()),
+            entity.stats.unwrap_or(ENEMY_STATS),
+            Position {
+                direction: entity.direction.clone(),
+                x: entity.x,
+                z: entity.z,
+            },
+            PbrBundle {
+                mesh: mesh.clone(),
+                material: material.clone(),
+                transform: Transform::from_xyz(entity.x as f32, 0.3, entity.z as f32),
+                ..default()
+            },
+        ));
+    }
+}
+
+pub fn setup_fight(mut commands: Commands, ui_font: Res<UiFont>) {
+    let text_style = TextStyle {
+        font: ui_font.0.clone(),
+        font_size: 12.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn((
+            FightPanel,
+            NodeBundle {
+                z_index: ZIndex::Global(115),
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(72.0),
+                    right: Val::Px(62.0),
+                    bottom: Val::Px(4.0),
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(4.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.85).into(),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((FightText, TextBundle::from_section("", text_style.clone())));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        margin: UiRect::top(Val::Px(2.0)),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for command in FIGHT_COMMANDS {
+                        parent
+                            .spawn((
+                                FightCommandButton(command),
+                                ButtonBundle {
+                                    style: Style {
+                                        width: Val::Px(44.0),
+                                        height: Val::Px(16.0),
+                                        justify_content: JustifyContent::Center,
+                                        align_items: AlignItems::Center,
+                                        margin: UiRect::all(Val::Px(1.0)),
+                                        ..default()
+                                    },
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn((
+                                    FightCommandText(command),
+                                    TextBundle::from_section(command.label(), text_style.clone()),
+                                ));
+                            });
+                    }
+                });
+        });
+}
+
+pub fn start_fight(
+    mut fight_events: EventReader<FightEvent>,
+    mut active_fight: ResMut<ActiveFight>,
+    mut message_events: EventWriter<MessageEvent>,
+) {
+    let Some(event) = fight_events.iter().last() else {
+        return;
+    };
+    if active_fight.0.is_some() {
+        return;
+    }
+    active_fight.0 = Some(Fight {
+        enemy: event.0,
+        selected: 0,
+    });
+    message_events.send(MessageEvent("".to_owned()));
+}
+
+fn spawn_damage_number(
+    commands: &mut Commands,
+    ui_font: &UiFont,
+    value: String,
+    color: Color,
+    top: f32,
+) {
+    commands.spawn((
+        DamageNumber(Timer::from_seconds(DAMAGE_NUMBER_SECONDS, TimerMode::Once)),
+        TextBundle {
+            z_index: ZIndex::Global(116),
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(48.0),
+                top: Val::Percent(top),
+                ..default()
+            },
+            ..TextBundle::from_section(
+                value,
+                TextStyle {
+                    font: ui_font.0.clone(),
+                    font_size: 24.0,
+                    color,
+                },
+            )
+        },
+    ));
+}
+
+/// Runs one round: the player's command, then the enemy's attack.
+#[allow(clippy::too_many_arguments)]
+pub fn update_fight(
+    mut commands: Commands,
+    time: Res<Time>,
+    ui_font: Res<UiFont>,
+    dungeon_level: Res<DungeonLevel>,
+    mut action_events: EventReader<ActionEvent>,
+    button_query: Query<(&Interaction, &FightCommandButton), Changed<Interaction>>,
+    player_query: Query<(Entity, &Transform, &Position), With<Player>>,
+    mut enemy_query: Query<(&EntityIid, &Position, &mut Stats), With<Enemy>>,
+    inventory: Res<Inventory>,
+    mut player_stats: ResMut<PlayerStats>,
+    mut active_fight: ResMut<ActiveFight>,
+    mut world_state: ResMut<WorldState>,
+    mut rng: Local<Option<Rng>>,
+    mut message_events: EventWriter<MessageEvent>,
+) {
+    let actions: Vec<Action> = action_events.iter().map(|event| event.0).collect();
+    let Some(fight) = active_fight.0.as_mut() else {
+        return;
+    };
+    let Ok((player, transform, position)) = player_query.get_single() else {
+        return;
+    };
+    let equipped = inventory.equipped_stats(&player_stats.stats);
+    let Ok((iid, enemy_position, mut enemy_stats)) = enemy_query.get_mut(fight.enemy) else {
+        active_fight.0 = None;
+        return;
+    };
+    let rng = rng.get_or_insert_with(|| Rng(time.raw_elapsed().as_nanos() as u64));
+
+    let count = FIGHT_COMMANDS.len();
+    let mut command = None;
+    for action in actions {
+        match action {
+            Action::TurnLeft | Action::StrafeLeft => {
+                fight.selected = (fight.selected + count - 1) % count
+            }
+            Action::TurnRight | Action::StrafeRight => {
+                fight.selected = (fight.selected + 1) % count
+            }
+            Action::Interact => command = Some(FIGHT_COMMANDS[fight.selected]),
+            _ => (),
+        }
+    }
+    if let Some((_, button)) = button_query
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Pressed)
+    {
+        command = Some(button.0);
+    }
+    let Some(command) = command else {
+        return;
+    };
+
+    let mut defending = false;
+    match command {
+        FightCommand::Attack => {
+            commands.entity(player).insert(get_cannot_move_animator(
+                transform,
+                position,
+                enemy_position,
+            ));
+            let damage = equipped.damage_to(&enemy_stats, rng);
+            enemy_stats.hp -= damage;
+            spawn_damage_number(
+                &mut commands,
+                &ui_font,
+                damage.to_string(),
+                ENEMY_DAMAGE_COLOR,
+                ENEMY_DAMAGE_TOP,
+            );
+            if enemy_stats.hp <= 0 {
+                world_state.remove(dungeon_level.0, &iid.0);
+                commands.entity(fight.enemy).despawn_recursive();
+                active_fight.0 = None;
+                let message = if player_stats.gain_xp(enemy_stats.max_hp as u32) {
+                    format!("Level up! Lv {}", player_stats.level)
+                } else {
+                    "Victory".to_owned()
+                };
+                message_events.send(MessageEvent(message));
+                return;
+            }
+        }
+        FightCommand::Defend => defending = true,
+        FightCommand::Flee => {
+            if rng.range(0, 2) == 0 {
+                active_fight.0 = None;
+                message_events.send(MessageEvent("Escaped".to_owned()));
+                return;
+            }
+            message_events.send(MessageEvent("Can't escape".to_owned()));
+        }
+    }
+
+    let mut damage = enemy_stats.damage_to(&equipped, rng);
+    if defending {
+        damage /= 2;
+    }
+    let stats = &mut player_stats.stats;
+    stats.hp = (stats.hp - damage).max(0);
+    spawn_damage_number(
+        &mut commands,
+        &ui_font,
+        damage.to_string(),
+        PLAYER_DAMAGE_COLOR,
+        PLAYER_DAMAGE_TOP,
+    );
+    if stats.hp == 0 {
+        active_fight.0 = None;
+        message_events.send(MessageEvent("You collapse".to_owned()));
+    }
+}
+
+pub fn update_fight_ui(
+    active_fight: Res<ActiveFight>,
+    player_stats: Res<PlayerStats>,
+    enemy_query: Query<&Stats, With<Enemy>>,
+    mut panel_query: Query<&mut Style, With<FightPanel>>,
+    mut text_query: Query<&mut Text, With<FightText>>,
+    mut command_query: Query<(&mut Text, &FightCommandText), Without<FightText>>,
+) {
+    let mut style = panel_query.single_mut();
+    let Some(fight) = active_fight.0.as_ref() else {
+        if style.display != Display::None {
+            style.display = Display::None;
+        }
+        return;
+    };
+    style.display = Display::Flex;
+    let Ok(enemy) = enemy_query.get(fight.enemy) else {
+        return;
+    };
+    let player = &player_stats.stats;
+    text_query.single_mut().sections[0].value = format!(
+        "Enemy HP {}/{}   You HP {}/{}",
+        enemy.hp, enemy.max_hp, player.hp, player.max_hp
+    );
+    for (mut text, command_text) in &mut command_query {
+        text.sections[0].style.color = if FIGHT_COMMANDS[fight.selected] == command_text.0 {
+            SELECTED_COLOR
+        } else {
+            Color::WHITE
+        };
+    }
+}
+
+pub fn update_damage_numbers(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut DamageNumber, &mut Style, &mut Text)>,
+) {
+    for (entity, mut damage_number, mut style, mut text) in &mut query {
+        damage_number.0.tick(time.delta());
+        if damage_number.0.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        if let Val::Percent(top) = style.top {
+            style.top = Val::Percent(top - time.delta_seconds() * 10.0);
+        }
+        let alpha = 1.0 - damage_number.0.percent();
+        text.sections[0].style.color.set_a(alpha);
+    }
+}
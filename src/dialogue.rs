@@ -0,0 +1,501 @@
+use crate::dungeon::{Dungeon, DungeonLevel};
+use crate::input::{Action, ActionEvent};
+use crate::inventory::Inventory;
+use crate::player::{LevelTransition, Player};
+use crate::position::{Direction, Position};
+use crate::{SpawnDungeonEvent, UiFont, NORMAL_BUTTON};
+use ::serde::{Deserialize, Serialize};
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy::utils::BoxedFuture;
+use std::collections::{HashMap, HashSet};
+
+const CHARS_PER_SECOND: f32 = 30.0;
+const SELECTED_COLOR: Color = Color::rgb(1.0, 1.0, 0.4);
+
+/// A dialogue tree. Nodes are keyed by name and the conversation starts at `start`.
+#[derive(Deserialize, Clone, TypeUuid, TypePath)]
+#[uuid = "6d3f2a4e-93c1-4f58-a7a2-1f0b8e9c4d21"]
+pub struct Dialogue {
+    pub start: String,
+    pub nodes: HashMap<String, DialogueNode>,
+}
+
+/// Pages are shown one after another. After the last page the player picks one
+/// of the available choices, or the dialogue moves on to `next` (or ends).
+#[derive(Deserialize, Clone)]
+pub struct DialogueNode {
+    pub pages: Vec<String>,
+    #[serde(default)]
+    pub choices: Vec<Choice>,
+    /// Run when the node is entered.
+    #[serde(default)]
+    pub actions: Vec<DialogueAction>,
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Choice {
+    pub text: String,
+    #[serde(default)]
+    pub next: Option<String>,
+    /// The choice is only offered when all of these hold.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    Flag(String),
+    NotFlag(String),
+    HasItem(String),
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum DialogueAction {
+    SetFlag(String),
+    ClearFlag(String),
+    GiveItem(String),
+    Warp(Warp),
+}
+
+/// Moves the player to (`x`, `z`) of `level`, or of the current level if unset.
+#[derive(Deserialize, Clone)]
+pub struct Warp {
+    #[serde(default)]
+    pub level: Option<String>,
+    pub x: i32,
+    pub z: i32,
+    #[serde(default)]
+    pub direction: Option<Direction>,
+}
+
+/// Where an entity's dialogue comes from: written inline in its LDtk field, or
+/// a `.dialogue.json` file under `assets/`.
+#[derive(Clone)]
+pub enum DialogueSource {
+    Inline(Dialogue),
+    Asset(String),
+}
+impl DialogueSource {
+    /// Paths ending with `.json` are assets, anything else is parsed as an inline script.
+    pub fn parse(value: &str) -> Option<DialogueSource> {
+        if value.ends_with(".json") {
+            return Some(DialogueSource::Asset(value.to_owned()));
+        }
+        match serde_json::from_str(value) {
+            Ok(dialogue) => Some(DialogueSource::Inline(dialogue)),
+            Err(error) => {
+                warn!("Invalid dialogue script: {}", error);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DialogueLoader;
+impl AssetLoader for DialogueLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let dialogue: Dialogue = serde_json::from_slice(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(dialogue));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["dialogue.json"]
+    }
+}
+
+#[derive(Event)]
+pub struct DialogueEvent(pub DialogueSource);
+
+/// Flags set and cleared by dialogue actions.
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
+pub struct DialogueFlags(pub HashSet<String>);
+
+pub struct DialogueState {
+    dialogue: Dialogue,
+    node: String,
+    page: usize,
+    /// Characters of the page revealed so far.
+    revealed: f32,
+    selected: usize,
+    /// Whether the actions of the node have run.
+    entered: bool,
+}
+impl DialogueState {
+    fn new(dialogue: Dialogue) -> Self {
+        DialogueState {
+            node: dialogue.start.clone(),
+            dialogue,
+            page: 0,
+            revealed: 0.0,
+            selected: 0,
+            entered: false,
+        }
+    }
+
+    fn enter(&mut self, node: String) {
+        self.node = node;
+        self.page = 0;
+        self.revealed = 0.0;
+        self.selected = 0;
+        self.entered = false;
+    }
+
+    fn node(&self) -> Option<&DialogueNode> {
+        self.dialogue.nodes.get(&self.node)
+    }
+
+    fn page_text(&self) -> &str {
+        self.node()
+            .and_then(|node| node.pages.get(self.page))
+            .map(|page| page.as_str())
+            .unwrap_or("")
+    }
+
+    fn page_revealed(&self) -> bool {
+        self.revealed as usize >= self.page_text().chars().count()
+    }
+
+    fn last_page(&self) -> bool {
+        self.node()
+            .map(|node| self.page + 1 >= node.pages.len())
+            .unwrap_or(true)
+    }
+
+    fn choices(&self, flags: &DialogueFlags, inventory: &Inventory) -> Vec<&Choice> {
+        let holds = |condition: &Condition| match condition {
+            Condition::Flag(flag) => flags.0.contains(flag),
+            Condition::NotFlag(flag) => !flags.0.contains(flag),
+            Condition::HasItem(item) => inventory.has(item),
+        };
+        self.node()
+            .map(|node| {
+                node.choices
+                    .iter()
+                    .filter(|choice| choice.conditions.iter().all(holds))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// The dialogue being shown. Player moves and door interaction wait while it is set.
+#[derive(Resource, Default)]
+pub struct ActiveDialogue(pub Option<DialogueState>);
+
+#[derive(Component)]
+pub struct DialoguePanel;
+
+#[derive(Component)]
+pub struct DialogueText;
+
+#[derive(Component)]
+pub struct DialogueChoices;
+
+#[derive(Component)]
+pub struct ChoiceButton(pub usize);
+
+/// Keeps the dialogue assets of the current level loaded.
+#[derive(Resource, Default)]
+pub struct DialogueHandles(pub Vec<Handle<Dialogue>>);
+
+pub fn load_dialogues(
+    asset_server: Res<AssetServer>,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    spawn_events: EventReader<SpawnDungeonEvent>,
+    mut handles: ResMut<DialogueHandles>,
+) {
+    if spawn_events.is_empty() {
+        return;
+    }
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    handles.0 = level
+        .entities()
+        .iter()
+        .filter_map(|entity| match &entity.dialogue {
+            Some(DialogueSource::Asset(path)) => Some(asset_server.load(path.as_str())),
+            _ => None,
+        })
+        .collect();
+}
+
+pub fn setup_dialogue(mut commands: Commands, ui_font: Res<UiFont>) {
+    commands
+        .spawn((
+            DialoguePanel,
+            NodeBundle {
+                z_index: ZIndex::Global(115),
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(72.0),
+                    right: Val::Px(62.0),
+                    bottom: Val::Px(4.0),
+                    min_height: Val::Px(48.0),
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(4.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.85).into(),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                DialogueText,
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: ui_font.0.clone(),
+                        font_size: 12.0,
+                        color: Color::WHITE,
+                    },
+                ),
+            ));
+            parent.spawn((
+                DialogueChoices,
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        margin: UiRect::top(Val::Px(2.0)),
+                        ..default()
+                    },
+                    ..default()
+                },
+            ));
+        });
+}
+
+pub fn start_dialogue(
+    asset_server: Res<AssetServer>,
+    dialogues: Res<Assets<Dialogue>>,
+    mut dialogue_events: EventReader<DialogueEvent>,
+    mut active_dialogue: ResMut<ActiveDialogue>,
+) {
+    let Some(event) = dialogue_events.iter().last() else {
+        return;
+    };
+    let dialogue = match &event.0 {
+        DialogueSource::Inline(dialogue) => Some(dialogue.clone()),
+        DialogueSource::Asset(path) => dialogues.get(&asset_server.load(path.as_str())).cloned(),
+    };
+    let Some(dialogue) = dialogue else {
+        warn!("Dialogue is not loaded yet");
+        return;
+    };
+    active_dialogue.0 = Some(DialogueState::new(dialogue));
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn advance_dialogue(
+    mut commands: Commands,
+    time: Res<Time>,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    mut action_events: EventReader<ActionEvent>,
+    choice_query: Query<(&Interaction, &ChoiceButton), Changed<Interaction>>,
+    player_query: Query<&Position, With<Player>>,
+    mut active_dialogue: ResMut<ActiveDialogue>,
+    mut flags: ResMut<DialogueFlags>,
+    mut inventory: ResMut<Inventory>,
+    mut level_transition: LevelTransition,
+) {
+    let actions: Vec<Action> = action_events.iter().map(|event| event.0).collect();
+    let Some(state) = active_dialogue.0.as_mut() else {
+        return;
+    };
+
+    if !state.entered {
+        state.entered = true;
+        for action in state
+            .node()
+            .map(|node| node.actions.clone())
+            .unwrap_or_default()
+        {
+            match action {
+                DialogueAction::SetFlag(flag) => {
+                    flags.0.insert(flag);
+                }
+                DialogueAction::ClearFlag(flag) => {
+                    flags.0.remove(&flag);
+                }
+                DialogueAction::GiveItem(item) => {
+                    inventory.items.push(item);
+                }
+                DialogueAction::Warp(warp) => {
+                    let Ok(player) = player_query.get_single() else {
+                        continue;
+                    };
+                    let index = match &warp.level {
+                        Some(level) => match dungeon.level_index(level) {
+                            Some(index) => index,
+                            None => {
+                                warn!("Unknown warp level {}", level);
+                                continue;
+                            }
+                        },
+                        None => dungeon_level.0,
+                    };
+                    if dungeon.levels[index].get_tile(warp.x, warp.z).is_none() {
+                        warn!(
+                            "Warp target ({}, {}) is not on the floor of {}",
+                            warp.x, warp.z, dungeon.levels[index].identifier
+                        );
+                        continue;
+                    }
+                    let arrival = Position {
+                        direction: warp.direction.unwrap_or(player.direction.clone()),
+                        x: warp.x,
+                        z: warp.z,
+                    };
+                    level_transition.go(&mut commands, index, Some(arrival));
+                }
+            }
+        }
+    }
+
+    let choices = state.choices(&flags, &inventory).len();
+    let choosing = state.last_page() && state.page_revealed() && choices > 0;
+    let clicked = choice_query
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Pressed)
+        .map(|(_, choice_button)| choice_button.0);
+
+    let mut confirmed = None;
+    let mut advanced = false;
+    let mut selected = state.selected;
+    for action in actions {
+        match action {
+            Action::Interact => advanced = true,
+            Action::Forward if choosing => selected = (selected + choices - 1) % choices,
+            Action::Backward if choosing => selected = (selected + 1) % choices,
+            _ => (),
+        }
+    }
+    if choosing && clicked.is_some() {
+        confirmed = clicked;
+    } else if choosing && advanced {
+        confirmed = Some(selected);
+    }
+
+    state.selected = selected;
+    let next = if let Some(index) = confirmed {
+        // a click on a choice that is gone by now is ignored
+        let choices = state.choices(&flags, &inventory);
+        let Some(choice) = choices.get(index) else {
+            return;
+        };
+        choice.next.clone()
+    } else if !advanced {
+        if !state.page_revealed() {
+            state.revealed += time.delta_seconds() * CHARS_PER_SECOND;
+        }
+        return;
+    } else if !state.page_revealed() {
+        state.revealed = state.page_text().chars().count() as f32;
+        return;
+    } else if !state.last_page() {
+        state.page += 1;
+        state.revealed = 0.0;
+        return;
+    } else {
+        state.node().and_then(|node| node.next.clone())
+    };
+
+    match next {
+        Some(node) => state.enter(node),
+        None => active_dialogue.0 = None,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_dialogue_ui(
+    mut commands: Commands,
+    ui_font: Res<UiFont>,
+    active_dialogue: Res<ActiveDialogue>,
+    flags: Res<DialogueFlags>,
+    inventory: Res<Inventory>,
+    mut panel_query: Query<&mut Style, With<DialoguePanel>>,
+    mut text_query: Query<&mut Text, With<DialogueText>>,
+    choices_query: Query<Entity, With<DialogueChoices>>,
+    mut shown: Local<Option<(String, usize, usize, bool)>>,
+) {
+    if !active_dialogue.is_changed() {
+        return;
+    }
+    let mut style = panel_query.single_mut();
+    let Some(state) = active_dialogue.0.as_ref() else {
+        style.display = Display::None;
+        *shown = None;
+        return;
+    };
+    style.display = Display::Flex;
+
+    let mut text = text_query.single_mut();
+    text.sections[0].value = state
+        .page_text()
+        .chars()
+        .take(state.revealed as usize)
+        .collect();
+
+    // choices are only rebuilt when they change, so that buttons keep their interaction
+    let choosing = state.last_page() && state.page_revealed();
+    let key = (state.node.clone(), state.page, state.selected, choosing);
+    if shown.as_ref() == Some(&key) {
+        return;
+    }
+    *shown = Some(key);
+    let choices = choices_query.single();
+    commands.entity(choices).despawn_descendants();
+    if !choosing {
+        return;
+    }
+    commands.entity(choices).with_children(|parent| {
+        for (index, choice) in state.choices(&flags, &inventory).iter().enumerate() {
+            let color = if index == state.selected {
+                SELECTED_COLOR
+            } else {
+                Color::WHITE
+            };
+            parent
+                .spawn((
+                    ChoiceButton(index),
+                    ButtonBundle {
+                        style: Style {
+                            height: Val::Px(14.0),
+                            align_items: AlignItems::Center,
+                            padding: UiRect::horizontal(Val::Px(2.0)),
+                            margin: UiRect::all(Val::Px(1.0)),
+                            ..default()
+                        },
+                        background_color: NORMAL_BUTTON.into(),
+                        ..default()
+                    },
+                ))
+                .with_children(|parent| {
+                    parent.spawn(TextBundle::from_section(
+                        choice.text.clone(),
+                        TextStyle {
+                            font: ui_font.0.clone(),
+                            font_size: 12.0,
+                            color,
+                        },
+                    ));
+                });
+        }
+    });
+}
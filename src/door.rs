@@ -0,0 +1,105 @@
+use crate::dungeon::{Dungeon, DungeonLevel};
+use crate::input::{Action, ActionEvent};
+use crate::inventory::Inventory;
+use crate::player::{Player, PlayerLock};
+use crate::position::{Direction, Position};
+use crate::world::WorldState;
+use crate::MessageEvent;
+use ::serde::{Deserialize, Serialize};
+use bevy::prelude::*;
+use bevy_tweening::{lens::*, *};
+use std::time::Duration;
+
+const DOOR_OPEN_HEIGHT: f32 = 0.95;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DoorState {
+    Open,
+    Closed,
+    Locked,
+}
+
+#[derive(Component)]
+pub struct DoorPanel(pub usize);
+
+pub fn get_door_transform(direction: &Direction, x: f32, z: f32, state: DoorState) -> Transform {
+    let mut transform = Transform::from_translation(
+        Vec3::new(x, 0.5, z)
+            + match direction {
+                Direction::Up => Vec3::NEG_Z,
+                Direction::Right => Vec3::X,
+                Direction::Down => Vec3::Z,
+                Direction::Left => Vec3::NEG_X,
+            } * 0.5,
+    );
+    if matches!(direction, Direction::Right | Direction::Left) {
+        transform.rotate_y(std::f32::consts::PI * 0.5);
+    }
+    if state == DoorState::Open {
+        transform.translation.y += DOOR_OPEN_HEIGHT;
+    }
+    transform
+}
+
+pub fn get_door_animator(transform: &Transform, end: Vec3) -> Animator<Transform> {
+    Animator::new(Tween::new(
+        EaseFunction::QuadraticInOut,
+        Duration::from_millis(400),
+        TransformPositionLens {
+            start: transform.translation,
+            end,
+        },
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn interact_door(
+    mut action_events: EventReader<ActionEvent>,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    mut world_state: ResMut<WorldState>,
+    inventory: Res<Inventory>,
+    player_lock: PlayerLock,
+    player_query: Query<&Position, With<Player>>,
+    mut commands: Commands,
+    panel_query: Query<(Entity, &Transform, &DoorPanel)>,
+    mut message_events: EventWriter<MessageEvent>,
+) {
+    let interacted = action_events
+        .iter()
+        .any(|event| event.0 == Action::Interact);
+    if player_query.is_empty() || !interacted || player_lock.is_locked() {
+        return;
+    }
+    let position = player_query.single();
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    let Some((door_index, door)) = level.get_door(position.x, position.z, &position.direction)
+    else {
+        return;
+    };
+
+    let state = world_state.door_state(dungeon_level.0, door);
+    let new_state = match state {
+        DoorState::Open => DoorState::Closed,
+        DoorState::Closed => DoorState::Open,
+        DoorState::Locked => match &door.key {
+            Some(key) if inventory.has(key) => {
+                message_events.send(MessageEvent("Unlocked".to_owned()));
+                DoorState::Open
+            }
+            _ => {
+                message_events.send(MessageEvent("Locked".to_owned()));
+                return;
+            }
+        },
+    };
+    world_state.set_door_state(dungeon_level.0, door, new_state);
+
+    for (entity, transform, _) in panel_query.iter().filter(|(_, _, p)| p.0 == door_index) {
+        let end = get_door_transform(&door.direction, door.x as f32, door.z as f32, new_state)
+            .translation;
+        commands
+            .entity(entity)
+            .insert(get_door_animator(transform, end));
+    }
+}
@@ -1,95 +1,721 @@
-use crate::ldtk::Coordinate as Ldtk;
-use crate::position::{get_transform, Direction};
-use crate::SpawnDungeonEvent;
+use crate::autolayer::{apply_rules, AutoTile, IntGrid};
+use crate::cat::CatBehaviour;
+use crate::combat::{Stats, ENEMY_STATS};
+use crate::dialogue::DialogueSource;
+use crate::door::{get_door_transform, DoorPanel};
+use crate::field::{get_fields, FieldValue};
+use crate::generator::DungeonSeed;
+use crate::ldtk::{
+    Coordinate as Ldtk, EntityInstance, IntGridValueDefinition, LayerDefinition, LayerInstance,
+    Level as LdtkLevel, TilesetDefinition, WorldLayout,
+};
+use crate::player::Player;
+use crate::position::{get_transform, Direction, Position, DIRECTIONS};
+use crate::world::WorldState;
+use crate::{DespawnDungeonEvent, SpawnDungeonEvent};
 use ::serde::{Deserialize, Serialize};
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
 use bevy::prelude::*;
-use std::collections::HashSet;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy::utils::BoxedFuture;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::From;
 use std::f32::consts::PI;
-use std::iter::FromIterator;
+use std::fmt;
+use std::path::Path;
 use std::str::FromStr;
 
-#[derive(Resource, Clone)]
+#[derive(Resource, Clone, TypeUuid, TypePath)]
+#[uuid = "b4e2c7d1-5a3f-4e86-9c0d-7f1a2b3c4d5e"]
 pub struct Dungeon {
     pub levels: Vec<Level>,
 }
 impl FromWorld for Dungeon {
     fn from_world(_: &mut World) -> Self {
+        Dungeon::embedded()
+    }
+}
+impl Dungeon {
+    /// The level compiled into the binary, used until `level.ldtk` has been
+    /// loaded by the asset server. External levels aren't supported here.
+    pub fn embedded() -> Self {
         let lgtk = serde_json::from_str::<Ldtk>(include_str!("../assets/level.ldtk"))
             .expect("Failed to open level.ldtk");
         Dungeon::from(&lgtk)
     }
+
+    pub fn level_index(&self, identifier: &str) -> Option<usize> {
+        self.levels
+            .iter()
+            .position(|level| level.identifier == identifier)
+    }
+
+    /// The entity a link points to.
+    pub fn get_linked(&self, link: &EntityLink) -> Option<&EventEntity> {
+        self.levels.get(link.level)?.get_entity_by_iid(&link.iid)
+    }
+
+    /// Where walking off the edge of `level_index` onto `position` leads to:
+    /// the level of the same world with floor on that spot, and the player's
+    /// position in it.
+    pub fn get_neighbour(
+        &self,
+        level_index: usize,
+        position: &Position,
+    ) -> Option<(usize, Position)> {
+        let level = self.levels.get(level_index)?;
+        if (0..level.width).contains(&position.x) && (0..level.length).contains(&position.z) {
+            return None;
+        }
+        let origin = level.world_position.as_ref()?;
+        let (x, z) = (origin.x + position.x, origin.z + position.z);
+        self.levels
+            .iter()
+            .enumerate()
+            .find_map(|(index, neighbour)| {
+                let world_position = neighbour
+                    .world_position
+                    .as_ref()
+                    .filter(|world_position| world_position.world == origin.world)?;
+                let arrival = Position {
+                    direction: position.direction.clone(),
+                    x: x - world_position.x,
+                    z: z - world_position.z,
+                };
+                neighbour.get_tile(arrival.x, arrival.z)?;
+                Some((index, arrival))
+            })
+    }
+
+    /// Resolves where stepping onto `entity` in `level_index` leads to.
+    /// Stairs without an explicit `Level` field connect to the next/previous level.
+    pub fn get_destination(
+        &self,
+        level_index: usize,
+        entity: &EventEntity,
+    ) -> Option<(usize, Option<Position>)> {
+        let destination = entity.destination.as_ref()?;
+        // a warp linked to another entity leads onto it, whatever the level
+        if let Some(link) = entity.links("Target").first() {
+            let target = self.get_linked(link)?;
+            let arrival = Position {
+                direction: target.direction.clone(),
+                x: target.x,
+                z: target.z,
+            };
+            return Some((link.level, Some(arrival)));
+        }
+        let target = match &destination.level {
+            Some(identifier) => self.level_index(identifier),
+            None => match entity.entity_type {
+                EntityType::StairsDown => Some(level_index + 1),
+                EntityType::StairsUp => level_index.checked_sub(1),
+                _ => Some(level_index),
+            },
+        }
+        .filter(|index| *index < self.levels.len())?;
+        let level = &self.levels[target];
+        let arrival = destination
+            .position
+            .clone()
+            .filter(|p| p.x >= 0 && p.x < level.width && p.z >= 0 && p.z < level.length);
+        Some((target, arrival))
+    }
 }
+
+/// Levels saved in files of their own, with their path relative to the
+/// project. Their layers have to be read from there.
+pub fn get_external_levels(ldtk: &mut Ldtk) -> Vec<(&mut LdtkLevel, String)> {
+    ldtk.levels
+        .iter_mut()
+        .chain(
+            ldtk.worlds
+                .iter_mut()
+                .flat_map(|world| world.levels.iter_mut()),
+        )
+        .filter(|level| level.layer_instances.is_none())
+        .filter_map(|level| {
+            let path = level.external_rel_path.clone()?;
+            Some((level, path))
+        })
+        .collect()
+}
+
+/// Loads `.ldtk` projects, including levels saved as separate files.
+#[derive(Default)]
+pub struct LdtkLoader;
+impl AssetLoader for LdtkLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let mut ldtk: Ldtk = serde_json::from_slice(bytes)?;
+            let directory = load_context
+                .path()
+                .parent()
+                .unwrap_or(Path::new(""))
+                .to_owned();
+            for (level, path) in get_external_levels(&mut ldtk) {
+                // reading through the load context reloads the project when the level file changes
+                let bytes = load_context.read_asset_bytes(directory.join(path)).await?;
+                *level = serde_json::from_slice(&bytes)?;
+            }
+            load_context.set_default_asset(LoadedAsset::new(Dungeon::from(&ldtk)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ldtk"]
+    }
+}
+
+#[derive(Resource)]
+pub struct DungeonHandle(pub Handle<Dungeon>);
+impl FromWorld for DungeonHandle {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.get_resource::<AssetServer>().unwrap();
+        DungeonHandle(asset_server.load("level.ldtk"))
+    }
+}
+impl DungeonHandle {
+    /// The authored dungeon, or the embedded one while the asset is loading.
+    pub fn get(&self, dungeons: &Assets<Dungeon>) -> Dungeon {
+        dungeons
+            .get(&self.0)
+            .cloned()
+            .unwrap_or_else(Dungeon::embedded)
+    }
+}
+
+/// Swaps in the authored dungeon whenever `level.ldtk` (re)loads, and respawns
+/// the current level around the player. Random dungeons are left alone.
+#[allow(clippy::too_many_arguments)]
+pub fn reload_dungeon(
+    mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<Dungeon>>,
+    dungeons: Res<Assets<Dungeon>>,
+    dungeon_handle: Res<DungeonHandle>,
+    dungeon_seed: Res<DungeonSeed>,
+    dungeon_level: Res<DungeonLevel>,
+    player_query: Query<&Position, With<Player>>,
+    mut despawn_events: EventWriter<DespawnDungeonEvent>,
+    mut spawn_events: EventWriter<SpawnDungeonEvent>,
+) {
+    let reloaded = asset_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            *handle == dungeon_handle.0
+        }
+        AssetEvent::Removed { .. } => false,
+    });
+    if !reloaded || dungeon_seed.0.is_some() {
+        return;
+    }
+    let Some(dungeon) = dungeons.get(&dungeon_handle.0) else {
+        return;
+    };
+    let position = if dungeon_level.0 < dungeon.levels.len() {
+        player_query.get_single().ok().cloned()
+    } else {
+        commands.insert_resource(DungeonLevel(0));
+        None
+    };
+    commands.insert_resource(dungeon.clone());
+    despawn_events.send(DespawnDungeonEvent);
+    spawn_events.send(SpawnDungeonEvent(position));
+}
+
 #[derive(Clone)]
 pub struct Level {
+    pub identifier: String,
     pub width: i32,
     pub length: i32,
-    pub tiles: Vec<Tile>,
-    pub entities: Vec<EventEntity>,
+    tiles: Vec<Tile>,
+    entities: Vec<EventEntity>,
+    doors: Vec<Door>,
+    /// Set in GridVania and linear worlds, where touching levels are walked
+    /// between through their edges.
+    pub world_position: Option<WorldPosition>,
+    /// Index into `entities` by entity iid.
+    entity_index: HashMap<String, usize>,
+    /// Index into `tiles` for every cell, row by row.
+    tile_grid: Vec<Option<usize>>,
+    /// Indices into `entities` by cell, in level order.
+    entity_cells: HashMap<(i32, i32), Vec<usize>>,
+    /// Index into `doors` by door iid.
+    door_index: HashMap<String, usize>,
+    /// Index into `doors` by the tile and side of each door, on both sides
+    /// of its edge.
+    door_edges: HashMap<(i32, i32, Direction), usize>,
 }
 impl Level {
+    /// Doors are carved into the walls on both sides of their edge.
+    pub fn new(
+        identifier: String,
+        width: i32,
+        length: i32,
+        tiles: Vec<Tile>,
+        entities: Vec<EventEntity>,
+        doors: Vec<Door>,
+    ) -> Self {
+        let entity_index = entities
+            .iter()
+            .enumerate()
+            .map(|(index, entity)| (entity.iid.clone(), index))
+            .collect();
+        let mut tile_grid = vec![None; (width.max(0) * length.max(0)) as usize];
+        for (index, tile) in tiles.iter().enumerate() {
+            if (0..width).contains(&tile.x) && (0..length).contains(&tile.z) {
+                tile_grid[(tile.z * width + tile.x) as usize] = Some(index);
+            }
+        }
+        let mut entity_cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (index, entity) in entities.iter().enumerate() {
+            entity_cells
+                .entry((entity.x, entity.z))
+                .or_default()
+                .push(index);
+        }
+        let mut level = Level {
+            identifier,
+            width,
+            length,
+            tiles,
+            entities,
+            doors: vec![],
+            world_position: None,
+            entity_index,
+            tile_grid,
+            entity_cells,
+            door_index: HashMap::new(),
+            door_edges: HashMap::new(),
+        };
+        for door in doors {
+            level.add_door(door);
+        }
+        level
+    }
+
+    pub fn tiles(&self) -> &[Tile] {
+        &self.tiles
+    }
+
+    pub fn entities(&self) -> &[EventEntity] {
+        &self.entities
+    }
+
+    pub fn doors(&self) -> &[Door] {
+        &self.doors
+    }
+
+    /// The tile at the cell, for changes that keep it there.
+    fn tile_mut(&mut self, x: i32, z: i32) -> Option<&mut Tile> {
+        if !(0..self.width).contains(&x) || !(0..self.length).contains(&z) {
+            return None;
+        }
+        self.tile_grid[(z * self.width + x) as usize].map(|index| &mut self.tiles[index])
+    }
+
+    /// Puts a door in place of the wall on both sides of its edge.
+    pub fn add_door(&mut self, door: Door) {
+        let mut neighbour = Position {
+            direction: door.direction.clone(),
+            x: door.x,
+            z: door.z,
+        };
+        neighbour.go_forward();
+        let index = self.doors.len();
+        for (x, z, direction) in [
+            (door.x, door.z, door.direction.clone()),
+            (neighbour.x, neighbour.z, door.direction.reverse()),
+        ] {
+            if let Some(tile) = self.tile_mut(x, z) {
+                tile.walls.remove(&direction);
+                tile.doors.insert(direction.clone());
+            }
+            // the first door on an edge wins
+            self.door_edges.entry((x, z, direction)).or_insert(index);
+        }
+        self.door_index.insert(door.iid.clone(), index);
+        self.doors.push(door);
+    }
+
     pub fn get_tile(&self, x: i32, z: i32) -> Option<&Tile> {
-        self.tiles.iter().find(|t| t.x == x && t.z == z)
+        if !(0..self.width).contains(&x) || !(0..self.length).contains(&z) {
+            return None;
+        }
+        self.tile_grid[(z * self.width + x) as usize].map(|index| &self.tiles[index])
     }
 
-    pub fn get_entity(&self, x: i32, z: i32) -> Option<&EventEntity> {
-        let entity = self.entities.iter().find(|e| e.x == x && e.z == z);
-
-        return if entity.is_none() {
-            None
-        } else {
-            match entity.unwrap().entity_type {
-                EntityType::PlayerStart => None,
-                EntityType::Cat => entity,
+    /// Every entity on the tile, in level order.
+    pub fn entities_at(&self, x: i32, z: i32) -> impl Iterator<Item = &EventEntity> {
+        self.entity_cells
+            .get(&(x, z))
+            .into_iter()
+            .flatten()
+            .map(|index| &self.entities[*index])
+    }
+
+    /// Finds the door on the `direction` edge of the tile, from either side.
+    pub fn get_door(&self, x: i32, z: i32, direction: &Direction) -> Option<(usize, &Door)> {
+        if !self.get_tile(x, z)?.has_door(direction) {
+            return None;
+        }
+        let index = *self.door_edges.get(&(x, z, direction.clone()))?;
+        Some((index, &self.doors[index]))
+    }
+
+    pub fn get_entity_by_iid(&self, iid: &str) -> Option<&EventEntity> {
+        self.entity_index
+            .get(iid)
+            .and_then(|index| self.entities.get(*index))
+    }
+
+    pub fn get_door_by_iid(&self, iid: &str) -> Option<(usize, &Door)> {
+        let index = *self.door_index.get(iid)?;
+        Some((index, &self.doors[index]))
+    }
+
+    /// Whether a step from `from` towards `direction` lands on floor without
+    /// crossing a wall on either side of the edge. Doors count as open.
+    pub fn can_move(&self, from: &Position, direction: &Direction) -> bool {
+        let mut next = from.clone();
+        next.go(direction);
+        match (self.get_tile(from.x, from.z), self.get_tile(next.x, next.z)) {
+            (Some(tile), Some(next_tile)) => {
+                !tile.has_wall(direction) && !next_tile.has_wall(&direction.reverse())
             }
-        };
+            _ => false,
+        }
+    }
+
+    /// The tiles one step away from `position`, facing the way of the step.
+    pub fn neighbours(&self, position: &Position) -> Vec<Position> {
+        DIRECTIONS
+            .iter()
+            .filter(|direction| self.can_move(position, direction))
+            .map(|direction| {
+                let mut next = position.clone();
+                next.direction = direction.clone();
+                next.go(direction);
+                next
+            })
+            .collect()
+    }
+
+    /// The shortest walk from `from` to `to`, without `from`. Every step
+    /// faces the way it goes, and must also pass `can_step`, e.g. to keep off
+    /// closed doors.
+    pub fn find_path(
+        &self,
+        from: &Position,
+        to: &Position,
+        can_step: impl Fn(&Position, &Direction) -> bool,
+    ) -> Option<Vec<Position>> {
+        self.find_nearest(
+            from,
+            |position| position.x == to.x && position.z == to.z,
+            can_step,
+        )
+    }
+
+    /// Like `find_path`, towards the closest tile that passes `is_goal`.
+    pub fn find_nearest(
+        &self,
+        from: &Position,
+        is_goal: impl Fn(&Position) -> bool,
+        can_step: impl Fn(&Position, &Direction) -> bool,
+    ) -> Option<Vec<Position>> {
+        // every tile reached, with the step onto it and the tile it came from
+        let mut came_from: HashMap<(i32, i32), (Position, (i32, i32))> = HashMap::new();
+        let mut queue = VecDeque::from([from.clone()]);
+        while let Some(position) = queue.pop_front() {
+            if is_goal(&position) {
+                let mut path = vec![];
+                let mut current = (position.x, position.z);
+                while current != (from.x, from.z) {
+                    let (step, previous) = came_from.remove(&current)?;
+                    path.push(step);
+                    current = previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for next in self.neighbours(&position) {
+                let key = (next.x, next.z);
+                if key == (from.x, from.z)
+                    || came_from.contains_key(&key)
+                    || !can_step(&position, &next.direction)
+                {
+                    continue;
+                }
+                came_from.insert(key, (next.clone(), (position.x, position.z)));
+                queue.push_back(next);
+            }
+        }
+        None
+    }
+
+    /// Every tile that can be walked to from `start`, `start` included.
+    pub fn reachable_from(&self, start: &Position) -> HashSet<(i32, i32)> {
+        let mut reached = HashSet::from([(start.x, start.z)]);
+        let mut queue = VecDeque::from([start.clone()]);
+        while let Some(position) = queue.pop_front() {
+            for next in self.neighbours(&position) {
+                if reached.insert((next.x, next.z)) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        reached
+    }
+
+    /// The entity that reacts when stepped on: one with a message, dialogue
+    /// or destination that isn't an actor.
+    pub fn get_entity(&self, x: i32, z: i32) -> Option<&EventEntity> {
+        self.entities_at(x, z).find(|entity| {
+            !entity.is_actor()
+                && (entity.message.is_some()
+                    || entity.dialogue.is_some()
+                    || entity.destination.is_some())
+        })
     }
 }
+/// The top left corner of a level in its world, in tiles.
+#[derive(Clone)]
+pub struct WorldPosition {
+    pub world: usize,
+    pub x: i32,
+    pub z: i32,
+}
 #[derive(Clone)]
 pub struct Tile {
     pub x: i32,
     pub z: i32,
     pub walls: HashSet<Direction>,
+    pub doors: HashSet<Direction>,
+    pub terrain: Terrain,
 }
 impl Tile {
     pub fn has_wall(&self, direction: &Direction) -> bool {
         self.walls.contains(direction)
     }
+
+    pub fn has_door(&self, direction: &Direction) -> bool {
+        self.doors.contains(direction)
+    }
+}
+/// The floor of a tile, from the IntGrid layer.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum Terrain {
+    #[default]
+    Floor,
+    /// Can't be walked into.
+    Water,
+    /// Drops the player to the level below.
+    Pit,
+    /// Hurts the player on every step onto it.
+    Damage,
+    /// The player slides on until something stops them.
+    Ice,
+}
+impl FromStr for Terrain {
+    type Err = ();
+    fn from_str(input: &str) -> Result<Terrain, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "floor" => Ok(Terrain::Floor),
+            "water" => Ok(Terrain::Water),
+            "pit" => Ok(Terrain::Pit),
+            "damage" => Ok(Terrain::Damage),
+            "ice" => Ok(Terrain::Ice),
+            _ => Err(()),
+        }
+    }
+}
+/// What an IntGrid value stands for, by its identifier.
+enum GridCell {
+    Empty,
+    Solid,
+    Terrain(Terrain),
 }
 #[derive(Clone)]
 pub struct EventEntity {
+    /// Unique id of the entity, the LDtk iid for authored levels.
+    pub iid: String,
     pub x: i32,
     pub z: i32,
     pub entity_type: EntityType,
     pub direction: Direction,
     pub message: Option<String>,
+    pub destination: Option<Destination>,
+    pub behaviour: Option<CatBehaviour>,
+    pub dialogue: Option<DialogueSource>,
+    pub stats: Option<Stats>,
+    /// Item id of an Item entity.
+    pub item: Option<String>,
+    /// Custom field values by field identifier.
+    pub fields: HashMap<String, FieldValue>,
+}
+impl EventEntity {
+    pub fn field(&self, identifier: &str) -> Option<&FieldValue> {
+        self.fields.get(identifier)
+    }
+
+    /// Entities referenced by an EntityRef or `Array<EntityRef>` field.
+    pub fn links(&self, identifier: &str) -> Vec<EntityLink> {
+        self.field(identifier)
+            .map(|value| value.links())
+            .unwrap_or_default()
+    }
+
+    /// Cats, enemies, items and doors have their own components and are
+    /// looked up through those instead of the tile they start on.
+    pub fn is_actor(&self) -> bool {
+        matches!(
+            self.entity_type,
+            EntityType::Cat | EntityType::Enemy | EntityType::Item | EntityType::Door
+        )
+    }
+}
+/// A reference to an entity, possibly on another level.
+#[derive(Clone, PartialEq)]
+pub struct EntityLink {
+    pub level: usize,
+    pub iid: String,
+}
+/// Target of a stairs/warp entity. `None` fields fall back to the default
+/// level for the entity type and to the target level's PlayerStart.
+#[derive(Clone)]
+pub struct Destination {
+    pub level: Option<String>,
+    pub position: Option<Position>,
+}
+/// A door on the `direction` edge of tile (`x`, `z`). Its open/closed state
+/// during play lives in `WorldState`.
+#[derive(Clone)]
+pub struct Door {
+    pub iid: String,
+    pub x: i32,
+    pub z: i32,
+    pub direction: Direction,
+    pub locked: bool,
+    pub key: Option<String>,
 }
 #[derive(Clone)]
 pub enum EntityType {
     PlayerStart,
     Cat,
+    StairsDown,
+    StairsUp,
+    Warp,
+    Door,
+    Enemy,
+    Item,
+    /// Toggles its `Targets` when interacted with.
+    Switch,
+    /// Toggles its `Targets` when stepped on and again when left.
+    PressurePlate,
+    /// Blocks its tile while active.
+    Wall,
+    /// Makes its tile walkable while active.
+    Bridge,
+    Light,
+    /// An identifier the game has no type for. Spawned through the `EntityRegistry`.
+    Custom(String),
 }
 impl FromStr for EntityType {
     type Err = ();
     fn from_str(input: &str) -> Result<EntityType, Self::Err> {
-        return match input.to_lowercase().as_str() {
+        match input.to_lowercase().as_str() {
             "playerstart" => Ok(EntityType::PlayerStart),
             "cat" => Ok(EntityType::Cat),
+            "stairsdown" => Ok(EntityType::StairsDown),
+            "stairsup" => Ok(EntityType::StairsUp),
+            "warp" => Ok(EntityType::Warp),
+            "door" => Ok(EntityType::Door),
+            "enemy" => Ok(EntityType::Enemy),
+            "item" => Ok(EntityType::Item),
+            "switch" => Ok(EntityType::Switch),
+            "pressureplate" => Ok(EntityType::PressurePlate),
+            "wall" => Ok(EntityType::Wall),
+            "bridge" => Ok(EntityType::Bridge),
+            "light" => Ok(EntityType::Light),
             _ => Err(()),
-        };
+        }
+    }
+}
+
+/// A part of the project skipped while converting it, at grid cell (`x`, `z`)
+/// of a level. The validator reports these, the game only logs them.
+pub struct LoadWarning {
+    pub level: String,
+    pub x: i32,
+    pub z: i32,
+    pub message: String,
+}
+impl fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, {}): {}",
+            self.level, self.x, self.z, self.message
+        )
     }
 }
 
 impl From<&Ldtk> for Dungeon {
     fn from(ldtk: &Ldtk) -> Self {
+        let (dungeon, warnings) = Dungeon::from_ldtk(ldtk);
+        for warning in warnings.iter() {
+            warn!("{}", warning);
+        }
+        dungeon
+    }
+}
+
+impl Dungeon {
+    /// Converts the project, along with whatever couldn't be converted.
+    pub fn from_ldtk(ldtk: &Ldtk) -> (Self, Vec<LoadWarning>) {
         let default_grid_size = ldtk.default_grid_size;
-        Dungeon {
-            levels: ldtk
-                .levels
+        // multi-world projects keep their levels in `worlds`, the others at the top
+        let levels = get_world_positions(
+            ldtk.world_layout.as_ref(),
+            &ldtk.levels,
+            0,
+            default_grid_size,
+        )
+        .into_iter()
+        .chain(ldtk.worlds.iter().enumerate().flat_map(|(index, world)| {
+            get_world_positions(
+                world.world_layout.as_ref(),
+                &world.levels,
+                index + 1,
+                default_grid_size,
+            )
+        }))
+        .collect::<Vec<_>>();
+        let level_indices: HashMap<&str, usize> = levels
+            .iter()
+            .enumerate()
+            .map(|(index, (level, _))| (level.iid.as_str(), index))
+            .collect();
+        let mut warnings: Vec<LoadWarning> = vec![];
+        let dungeon = Dungeon {
+            levels: levels
                 .iter()
-                .map(|level| {
-                    let mut tiles: Vec<Tile> = vec![];
+                .map(|(level, world_position)| {
+                    let warning = |x: i32, z: i32, message: String| LoadWarning {
+                        level: level.identifier.clone(),
+                        x,
+                        z,
+                        message,
+                    };
+                    let mut tiles: HashMap<(i32, i32), Tile> = HashMap::new();
                     let mut entities: Vec<EventEntity> = vec![];
+                    let mut doors: Vec<Door> = vec![];
+                    let mut grid_cells: Vec<(i32, i32, GridCell)> = vec![];
                     let width = (level.px_wid / default_grid_size) as i32;
                     let length = (level.px_hei / default_grid_size) as i32;
 
@@ -97,115 +723,395 @@ impl From<&Ldtk> for Dungeon {
                         Option::Some(level).and_then(|level| level.layer_instances.as_ref())
                     {
                         for layer_instance in layer_instances.iter() {
-                            let grid_size = (layer_instance.c_wid, layer_instance.c_wid);
+                            let layer_def = ldtk
+                                .defs
+                                .layers
+                                .iter()
+                                .find(|layer| layer.uid == layer_instance.layer_def_uid);
+                            let tileset = layer_instance.tileset_def_uid.and_then(|uid| {
+                                ldtk.defs.tilesets.iter().find(|tileset| tileset.uid == uid)
+                            });
+                            // tiles placed by the auto-layer rules of IntGrid and AutoLayer layers
+                            if let (Some(layer_def), Some(tileset)) = (layer_def, tileset) {
+                                for tile in get_auto_tiles(
+                                    layer_instance,
+                                    layer_def,
+                                    layer_instances,
+                                    tileset,
+                                ) {
+                                    add_tile(
+                                        &mut tiles,
+                                        tile.x,
+                                        tile.z,
+                                        get_tile_walls(tileset, tile.tile_id, tile.flip),
+                                    );
+                                }
+                            }
                             if layer_instance.identifier == "Entities" {
-                                entities = layer_instance
+                                let mut fields_of = |entity: &EntityInstance| {
+                                    let mut messages = vec![];
+                                    let fields = get_fields(entity, &level_indices, &mut messages);
+                                    let (x, z) = (entity.grid[0] as i32, entity.grid[1] as i32);
+                                    warnings.extend(
+                                        messages.into_iter().map(|message| warning(x, z, message)),
+                                    );
+                                    fields
+                                };
+                                doors = layer_instance
                                     .entity_instances
                                     .iter()
+                                    .filter(|entity| {
+                                        matches!(
+                                            entity.identifier.parse::<EntityType>(),
+                                            Ok(EntityType::Door)
+                                        )
+                                    })
                                     .map(|entity| {
-                                        let direction = entity
-                                            .field_instances
-                                            .iter()
-                                            .find(|field_instance| {
-                                                field_instance.identifier == "Direction"
-                                            })
-                                            .and_then(|field_instance| {
-                                                match field_instance.value.as_ref() {
-                                                    Some(serde_json::Value::String(s)) => Some(s),
-                                                    _ => None,
-                                                }
-                                            })
-                                            .and_then(|s| {
-                                                Some(
-                                                    s.as_str()
-                                                        .parse::<Direction>()
-                                                        .unwrap_or(Direction::Right),
-                                                )
-                                            });
-                                        let identifier =
-                                            entity.identifier.parse::<EntityType>().unwrap();
-                                        let x = entity.grid[0] as i32;
-                                        let z = entity.grid[1] as i32;
-
-                                        let message = entity
-                                            .field_instances
-                                            .iter()
-                                            .find(|field_instance| {
-                                                field_instance.identifier == "Message"
-                                            })
-                                            .and_then(|field_instance| {
-                                                match field_instance.value.as_ref() {
-                                                    Some(serde_json::Value::String(s)) => {
-                                                        Some(s.to_owned())
-                                                    }
-                                                    _ => None,
-                                                }
-                                            });
-                                        EventEntity {
-                                            x: x,
-                                            z: z,
-                                            entity_type: identifier,
-                                            direction: direction.unwrap_or(Direction::Right),
-                                            message: message,
+                                        let fields = fields_of(entity);
+                                        Door {
+                                            iid: entity.iid.clone(),
+                                            x: entity.grid[0] as i32,
+                                            z: entity.grid[1] as i32,
+                                            direction: get_direction(&fields),
+                                            locked: fields
+                                                .get("Locked")
+                                                .and_then(|value| value.as_bool())
+                                                .unwrap_or(false),
+                                            key: fields
+                                                .get("Key")
+                                                .and_then(|value| value.as_str())
+                                                .map(|s| s.to_owned()),
                                         }
                                     })
                                     .collect();
-                            } else if layer_instance.identifier == "Tiles" {
-                                let tileset = layer_instance
-                                    .tileset_def_uid
-                                    .and_then(|uid| {
-                                        ldtk.defs.tilesets.iter().find(|tileset| tileset.uid == uid)
-                                    })
-                                    .unwrap();
-                                tiles = layer_instance
-                                    .grid_tiles
+                                entities = layer_instance
+                                    .entity_instances
                                     .iter()
-                                    .map(|tile| {
-                                        let x = (tile.px[0] / grid_size.0) as i32;
-                                        let z = (tile.px[1] / grid_size.1) as i32;
-                                        let directions = tileset
-                                            .custom_data
-                                            .iter()
-                                            .find(|d| d.tile_id == tile.t)
-                                            .map(|d| d.data.as_str())
-                                            .and_then(|s| {
-                                                if s.is_empty() {
-                                                    None
-                                                } else {
-                                                    Some(
-                                                        s.split(',')
-                                                            .map(|s| s.parse::<Direction>())
-                                                            .flat_map(|d| d)
-                                                            .collect(),
-                                                    )
-                                                }
-                                            })
-                                            .unwrap_or(vec![]);
-                                        Tile {
-                                            x: x,
-                                            z: z,
-                                            walls: HashSet::from_iter(directions),
-                                        }
+                                    .filter(|entity| {
+                                        !matches!(
+                                            entity.identifier.parse::<EntityType>(),
+                                            Ok(EntityType::Door)
+                                        )
                                     })
-                                    .collect()
+                                    .map(|entity| {
+                                        let entity_type =
+                                            entity.identifier.parse::<EntityType>().unwrap_or_else(
+                                                |_| EntityType::Custom(entity.identifier.clone()),
+                                            );
+                                        let fields = fields_of(entity);
+                                        let mut event_entity = EventEntity {
+                                            iid: entity.iid.clone(),
+                                            x: entity.grid[0] as i32,
+                                            z: entity.grid[1] as i32,
+                                            entity_type,
+                                            direction: get_direction(&fields),
+                                            message: None,
+                                            destination: None,
+                                            behaviour: None,
+                                            dialogue: None,
+                                            stats: None,
+                                            item: None,
+                                            fields,
+                                        };
+                                        let string = |id: &str| {
+                                            event_entity
+                                                .field(id)
+                                                .and_then(|value| value.as_str())
+                                                .map(|s| s.to_owned())
+                                        };
+                                        let message = string("Message");
+                                        let dialogue = string("Dialogue")
+                                            .as_deref()
+                                            .and_then(DialogueSource::parse);
+                                        let item = string("Item");
+                                        let stats = match event_entity.entity_type {
+                                            EntityType::Enemy => {
+                                                let stat = |id: &str, default: i32| {
+                                                    event_entity
+                                                        .field(id)
+                                                        .and_then(|value| value.as_i64())
+                                                        .map(|value| value as i32)
+                                                        .unwrap_or(default)
+                                                };
+                                                Some(Stats::new(
+                                                    stat("HP", ENEMY_STATS.hp),
+                                                    stat("Attack", ENEMY_STATS.attack),
+                                                    stat("Defense", ENEMY_STATS.defense),
+                                                ))
+                                            }
+                                            _ => None,
+                                        };
+                                        let behaviour = match event_entity.entity_type {
+                                            EntityType::Cat => Some(
+                                                string("Behaviour")
+                                                    .and_then(|s| s.parse().ok())
+                                                    .unwrap_or_default(),
+                                            ),
+                                            _ => None,
+                                        };
+                                        let destination = match event_entity.entity_type {
+                                            EntityType::StairsDown
+                                            | EntityType::StairsUp
+                                            | EntityType::Warp => Some(Destination {
+                                                level: string("Level"),
+                                                position: event_entity
+                                                    .field("Destination")
+                                                    .and_then(|value| value.as_point())
+                                                    .map(|(x, z)| Position {
+                                                        direction: event_entity.direction.clone(),
+                                                        x,
+                                                        z,
+                                                    }),
+                                            }),
+                                            _ => None,
+                                        };
+                                        event_entity.message = message;
+                                        event_entity.dialogue = dialogue;
+                                        event_entity.item = item;
+                                        event_entity.stats = stats;
+                                        event_entity.behaviour = behaviour;
+                                        event_entity.destination = destination;
+                                        event_entity
+                                    })
+                                    .collect();
+                            } else if layer_instance.identifier == "Tiles" {
+                                let Some(tileset) = tileset else {
+                                    warnings.push(warning(
+                                        0,
+                                        0,
+                                        "Tiles layer without a tileset is skipped".to_owned(),
+                                    ));
+                                    continue;
+                                };
+                                for tile in layer_instance.grid_tiles.iter() {
+                                    let x = (tile.px[0] / layer_instance.grid_size) as i32;
+                                    let z = (tile.px[1] / layer_instance.grid_size) as i32;
+                                    add_tile(
+                                        &mut tiles,
+                                        x,
+                                        z,
+                                        get_tile_walls(tileset, tile.t, tile.f),
+                                    );
+                                }
+                            } else if layer_instance.layer_instance_type == "IntGrid" {
+                                let values = layer_def
+                                    .map(|layer| layer.int_grid_values.as_slice())
+                                    .unwrap_or_default();
+                                grid_cells.extend(
+                                    layer_instance.int_grid_csv.iter().enumerate().map(
+                                        |(index, value)| {
+                                            let x = (index as i64 % layer_instance.c_wid) as i32;
+                                            let z = (index as i64 / layer_instance.c_wid) as i32;
+                                            let cell = get_grid_cell(values, *value)
+                                                .unwrap_or_else(|message| {
+                                                    warnings.push(warning(x, z, message));
+                                                    GridCell::Terrain(Terrain::Floor)
+                                                });
+                                            (x, z, cell)
+                                        },
+                                    ),
+                                );
                             }
                         }
                     }
-                    Level {
-                        width: width,
-                        length: length,
-                        tiles: tiles,
-                        entities: entities,
+                    // without a Tiles layer every open IntGrid cell is floor
+                    let floor_everywhere = tiles.is_empty();
+                    for (x, z, cell) in grid_cells.iter() {
+                        let terrain = match cell {
+                            GridCell::Solid => {
+                                tiles.remove(&(*x, *z));
+                                continue;
+                            }
+                            GridCell::Empty if !floor_everywhere => continue,
+                            GridCell::Empty => Terrain::Floor,
+                            GridCell::Terrain(terrain) => *terrain,
+                        };
+                        add_tile(&mut tiles, *x, *z, vec![]).terrain = terrain;
                     }
+                    // solid cells wall off the tiles around them
+                    for (x, z, _) in grid_cells
+                        .iter()
+                        .filter(|(_, _, cell)| matches!(cell, GridCell::Solid))
+                    {
+                        for direction in DIRECTIONS.iter() {
+                            let mut neighbour = Position {
+                                direction: direction.clone(),
+                                x: *x,
+                                z: *z,
+                            };
+                            neighbour.go_forward();
+                            if let Some(tile) = tiles.get_mut(&(neighbour.x, neighbour.z)) {
+                                tile.walls.insert(direction.reverse());
+                            }
+                        }
+                    }
+                    let mut tiles: Vec<Tile> = tiles.into_values().collect();
+                    tiles.sort_by_key(|tile| (tile.z, tile.x));
+                    let mut level = Level::new(
+                        level.identifier.clone(),
+                        width,
+                        length,
+                        tiles,
+                        entities,
+                        doors,
+                    );
+                    level.world_position = world_position.clone();
+                    level
                 })
                 .collect(),
-        }
+        };
+        (dungeon, warnings)
     }
 }
 
+/// Adds the walls to the tile at (`x`, `z`), creating it if needed.
+fn add_tile(
+    tiles: &mut HashMap<(i32, i32), Tile>,
+    x: i32,
+    z: i32,
+    walls: Vec<Direction>,
+) -> &mut Tile {
+    let tile = tiles.entry((x, z)).or_insert_with(|| Tile {
+        x,
+        z,
+        walls: HashSet::new(),
+        doors: HashSet::new(),
+        terrain: Terrain::Floor,
+    });
+    tile.walls.extend(walls);
+    tile
+}
+
+/// The walls of a tileset tile from its custom data such as "left,up",
+/// mirrored along with the tile.
+fn get_tile_walls(tileset: &TilesetDefinition, tile_id: i64, flip: i64) -> Vec<Direction> {
+    let Some(data) = tileset
+        .custom_data
+        .iter()
+        .find(|data| data.tile_id == tile_id)
+    else {
+        return vec![];
+    };
+    data.data
+        .split(',')
+        .flat_map(|s| s.parse::<Direction>())
+        .map(|direction| match direction {
+            Direction::Left | Direction::Right if flip & 1 != 0 => direction.reverse(),
+            Direction::Up | Direction::Down if flip & 2 != 0 => direction.reverse(),
+            _ => direction,
+        })
+        .collect()
+}
+
+/// The tiles of an auto layer as saved by LDtk, or evaluated from its rules
+/// when the file has none, e.g. after the IntGrid was edited by a tool.
+fn get_auto_tiles(
+    layer_instance: &LayerInstance,
+    layer_def: &LayerDefinition,
+    layer_instances: &[LayerInstance],
+    tileset: &TilesetDefinition,
+) -> Vec<AutoTile> {
+    if !layer_instance.auto_layer_tiles.is_empty() || layer_def.auto_rule_groups.is_empty() {
+        return layer_instance
+            .auto_layer_tiles
+            .iter()
+            .map(|tile| AutoTile {
+                x: (tile.px[0] / layer_instance.grid_size) as i32,
+                z: (tile.px[1] / layer_instance.grid_size) as i32,
+                tile_id: tile.t,
+                flip: tile.f,
+            })
+            .collect();
+    }
+    // AutoLayer layers read the IntGrid of another layer
+    let source = match layer_def.auto_source_layer_def_uid {
+        Some(uid) => layer_instances
+            .iter()
+            .find(|instance| instance.layer_def_uid == uid),
+        None => Some(layer_instance),
+    };
+    let Some(source) = source else {
+        return vec![];
+    };
+    let grid = IntGrid {
+        width: source.c_wid as i32,
+        height: source.c_hei as i32,
+        values: source.int_grid_csv.clone(),
+    };
+    apply_rules(
+        &layer_def.auto_rule_groups,
+        &layer_instance.optional_rules,
+        &grid,
+        tileset.c_wid,
+        layer_instance.seed,
+    )
+}
+
+/// Pairs the levels of a world with their position in it. Levels of linear
+/// layouts are laid out side by side in order, free layouts aren't walkable.
+fn get_world_positions<'a>(
+    layout: Option<&WorldLayout>,
+    levels: &'a [LdtkLevel],
+    world: usize,
+    grid_size: i64,
+) -> Vec<(&'a LdtkLevel, Option<WorldPosition>)> {
+    let mut offset = 0;
+    levels
+        .iter()
+        .map(|level| {
+            let (x, z) = match layout {
+                Some(WorldLayout::GridVania) => (level.world_x, level.world_y),
+                Some(WorldLayout::LinearHorizontal) => {
+                    offset += level.px_wid;
+                    (offset - level.px_wid, 0)
+                }
+                Some(WorldLayout::LinearVertical) => {
+                    offset += level.px_hei;
+                    (0, offset - level.px_hei)
+                }
+                Some(WorldLayout::Free) | None => return (level, None),
+            };
+            let world_position = WorldPosition {
+                world,
+                x: (x / grid_size) as i32,
+                z: (z / grid_size) as i32,
+            };
+            (level, Some(world_position))
+        })
+        .collect()
+}
+
+/// What an IntGrid value stands for, or why it can't be used.
+fn get_grid_cell(values: &[IntGridValueDefinition], value: i64) -> Result<GridCell, String> {
+    if value == 0 {
+        return Ok(GridCell::Empty);
+    }
+    let identifier = values
+        .iter()
+        .find(|definition| definition.value == value)
+        .and_then(|definition| definition.identifier.as_deref())
+        .unwrap_or_default();
+    match identifier.to_lowercase().as_str() {
+        "wall" | "solid" => Ok(GridCell::Solid),
+        _ => identifier
+            .parse()
+            .map(GridCell::Terrain)
+            .map_err(|_| format!("unknown IntGrid value {} ({})", value, identifier)),
+    }
+}
+
+fn get_direction(fields: &HashMap<String, FieldValue>) -> Direction {
+    fields
+        .get("Direction")
+        .and_then(|value| value.as_str())
+        .and_then(|s| s.parse::<Direction>().ok())
+        .unwrap_or(Direction::Right)
+}
+
 #[derive(Resource, Serialize, Deserialize)]
 pub struct DungeonLevel(pub usize);
 
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_dungeon(
     mut commands: Commands,
     asset_server: ResMut<AssetServer>,
@@ -213,6 +1119,7 @@ pub fn spawn_dungeon(
     mut meshes: ResMut<Assets<Mesh>>,
     dungeon: Res<Dungeon>,
     dungeon_level: Res<DungeonLevel>,
+    world_state: Res<WorldState>,
     spawn_events: EventReader<SpawnDungeonEvent>,
 ) {
     if spawn_events.is_empty() {
@@ -242,6 +1149,30 @@ pub fn spawn_dungeon(
         unlit: false,
         ..default()
     });
+    let material_water = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.15, 0.3, 0.6),
+        perceptual_roughness: 0.1,
+        reflectance: 0.8,
+        unlit: false,
+        ..default()
+    });
+    let material_pit = materials.add(StandardMaterial {
+        base_color: Color::BLACK,
+        unlit: true,
+        ..default()
+    });
+    let material_damage = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.5, 0.15, 0.1),
+        emissive: Color::rgb(0.4, 0.1, 0.0),
+        unlit: false,
+        ..default()
+    });
+    let material_ice = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.75, 0.9, 0.95),
+        perceptual_roughness: 0.15,
+        unlit: false,
+        ..default()
+    });
     let material_wall = materials.add(StandardMaterial {
         base_color_texture: Some(wall_texture),
         unlit: false,
@@ -260,10 +1191,16 @@ pub fn spawn_dungeon(
             ..default()
         });
     };
-    let spawn_floor = |commands: &mut Commands, x: f32, z: f32| {
+    let spawn_floor = |commands: &mut Commands, terrain: Terrain, x: f32, z: f32| {
         commands.spawn(MaterialMeshBundle {
             mesh: mesh_wall.clone(),
-            material: material_floor.clone(),
+            material: match terrain {
+                Terrain::Floor => material_floor.clone(),
+                Terrain::Water => material_water.clone(),
+                Terrain::Pit => material_pit.clone(),
+                Terrain::Damage => material_damage.clone(),
+                Terrain::Ice => material_ice.clone(),
+            },
             transform: Transform {
                 translation: Vec3::new(x, 0.0, z),
                 rotation: Quat::from_rotation_x(-PI * 0.5),
@@ -273,11 +1210,78 @@ pub fn spawn_dungeon(
         });
     };
 
-    for tile in level.tiles.iter() {
+    for tile in level.tiles().iter() {
         for direction in tile.walls.iter() {
             spawn_wall(&mut commands, direction, tile.x as f32, tile.z as f32);
         }
-        spawn_floor(&mut commands, tile.x as f32, tile.z as f32);
+        spawn_floor(&mut commands, tile.terrain, tile.x as f32, tile.z as f32);
+    }
+
+    // stairs, warps
+    let material_stairs_down = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.1, 0.1, 0.1),
+        unlit: false,
+        ..default()
+    });
+    let material_stairs_up = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.8, 0.8, 0.7),
+        unlit: false,
+        ..default()
+    });
+    let material_warp = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.4, 0.2, 0.8),
+        emissive: Color::rgb(0.2, 0.1, 0.4),
+        unlit: false,
+        ..default()
+    });
+    let mesh_marker = meshes.add(shape::Quad::new(Vec2::splat(0.6)).into());
+    for entity in level.entities().iter() {
+        let material = match entity.entity_type {
+            EntityType::StairsDown => material_stairs_down.clone(),
+            EntityType::StairsUp => material_stairs_up.clone(),
+            EntityType::Warp => material_warp.clone(),
+            _ => continue,
+        };
+        commands.spawn(MaterialMeshBundle {
+            mesh: mesh_marker.clone(),
+            material,
+            transform: Transform {
+                translation: Vec3::new(entity.x as f32, 0.01, entity.z as f32),
+                rotation: Quat::from_rotation_x(-PI * 0.5),
+                ..default()
+            },
+            ..default()
+        });
+    }
+
+    // doors
+    let material_door = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.45, 0.3, 0.15),
+        unlit: false,
+        ..default()
+    });
+    let material_locked_door = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.35, 0.35, 0.4),
+        metallic: 0.8,
+        unlit: false,
+        ..default()
+    });
+    let mesh_door = meshes.add(shape::Box::new(1.0, 1.0, 0.05).into());
+    for (index, door) in level.doors().iter().enumerate() {
+        let state = world_state.door_state(dungeon_level.0, door);
+        commands.spawn((
+            DoorPanel(index),
+            MaterialMeshBundle {
+                mesh: mesh_door.clone(),
+                material: if door.locked {
+                    material_locked_door.clone()
+                } else {
+                    material_door.clone()
+                },
+                transform: get_door_transform(&door.direction, door.x as f32, door.z as f32, state),
+                ..default()
+            },
+        ));
     }
 }
 
@@ -292,3 +1296,351 @@ fn get_wall_transform(direction: &Direction, x: f32, z: f32) -> Transform {
         + Vec3::new(0.0, 0.5, 0.0);
     transform
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A level from rows of `.` for floor and anything else for no tile.
+    fn level(rows: &[&str]) -> Level {
+        let tiles = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(z, row)| {
+                row.chars()
+                    .enumerate()
+                    .filter(|(_, c)| *c == '.')
+                    .map(move |(x, _)| Tile {
+                        x: x as i32,
+                        z: z as i32,
+                        walls: HashSet::new(),
+                        doors: HashSet::new(),
+                        terrain: Terrain::Floor,
+                    })
+            })
+            .collect();
+        Level::new(
+            "Test".to_owned(),
+            rows[0].len() as i32,
+            rows.len() as i32,
+            tiles,
+            vec![],
+            vec![],
+        )
+    }
+
+    fn add_wall(level: &mut Level, x: i32, z: i32, direction: Direction) {
+        level.tile_mut(x, z).unwrap().walls.insert(direction);
+    }
+
+    fn at(x: i32, z: i32) -> Position {
+        Position {
+            direction: Direction::Right,
+            x,
+            z,
+        }
+    }
+
+    fn cells(path: &[Position]) -> Vec<(i32, i32)> {
+        path.iter()
+            .map(|position| (position.x, position.z))
+            .collect()
+    }
+
+    #[test]
+    fn can_move_checks_walls_on_both_sides() {
+        let mut level = level(&["..."]);
+        assert!(level.can_move(&at(0, 0), &Direction::Right));
+
+        add_wall(&mut level, 0, 0, Direction::Right);
+        assert!(!level.can_move(&at(0, 0), &Direction::Right));
+        // a one-sided wall blocks the way back too
+        assert!(!level.can_move(&at(1, 0), &Direction::Left));
+
+        add_wall(&mut level, 2, 0, Direction::Left);
+        assert!(!level.can_move(&at(1, 0), &Direction::Right));
+    }
+
+    #[test]
+    fn can_move_needs_floor() {
+        let level = level(&[".#."]);
+        assert!(!level.can_move(&at(0, 0), &Direction::Right));
+        assert!(!level.can_move(&at(0, 0), &Direction::Left));
+        assert!(!level.can_move(&at(1, 0), &Direction::Right));
+    }
+
+    #[test]
+    fn neighbours_face_the_step() {
+        let level = level(&["...", "...", "..."]);
+        let mut around = level.neighbours(&at(1, 1));
+        around.sort_by_key(|position| (position.x, position.z));
+        assert_eq!(cells(&around), vec![(0, 1), (1, 0), (1, 2), (2, 1)]);
+        assert!(around.iter().any(|position| position.x == 1
+            && position.z == 0
+            && position.direction == Direction::Up));
+        assert_eq!(level.neighbours(&at(0, 0)).len(), 2);
+    }
+
+    #[test]
+    fn find_path_goes_straight() {
+        let level = level(&["...."]);
+        let path = level.find_path(&at(0, 0), &at(3, 0), |_, _| true).unwrap();
+        assert_eq!(cells(&path), vec![(1, 0), (2, 0), (3, 0)]);
+        assert!(path
+            .iter()
+            .all(|position| position.direction == Direction::Right));
+    }
+
+    #[test]
+    fn find_path_walks_around_walls() {
+        let mut level = level(&["...", "...", "..."]);
+        add_wall(&mut level, 0, 0, Direction::Right);
+        add_wall(&mut level, 1, 0, Direction::Left);
+        add_wall(&mut level, 0, 1, Direction::Right);
+        add_wall(&mut level, 1, 1, Direction::Left);
+        let path = level.find_path(&at(0, 0), &at(2, 0), |_, _| true).unwrap();
+        assert_eq!(
+            cells(&path),
+            vec![(0, 1), (0, 2), (1, 2), (2, 2), (2, 1), (2, 0)]
+        );
+    }
+
+    #[test]
+    fn find_path_to_itself_is_empty() {
+        let level = level(&[".."]);
+        assert!(level
+            .find_path(&at(1, 0), &at(1, 0), |_, _| true)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn find_path_fails_when_cut_off() {
+        let level = level(&["..#.."]);
+        assert!(level.find_path(&at(0, 0), &at(4, 0), |_, _| true).is_none());
+    }
+
+    #[test]
+    fn find_path_respects_can_step() {
+        let level = level(&["...", "..."]);
+        // the direct edge is closed, e.g. by a door
+        let path = level
+            .find_path(&at(0, 0), &at(1, 0), |from, direction| {
+                !(from.x == 0 && from.z == 0 && *direction == Direction::Right)
+            })
+            .unwrap();
+        assert_eq!(cells(&path), vec![(0, 1), (1, 1), (1, 0)]);
+    }
+
+    #[test]
+    fn find_nearest_takes_the_closest_goal() {
+        let level = level(&["....."]);
+        let path = level
+            .find_nearest(&at(1, 0), |tile| tile.x == 0 || tile.x == 4, |_, _| true)
+            .unwrap();
+        assert_eq!(cells(&path), vec![(0, 0)]);
+        assert!(path[0].direction == Direction::Left);
+    }
+
+    #[test]
+    fn reachable_from_stops_at_walls() {
+        let mut level = level(&["...", ".#.", "..."]);
+        add_wall(&mut level, 1, 0, Direction::Right);
+        add_wall(&mut level, 1, 2, Direction::Right);
+        let reached = level.reachable_from(&at(0, 0));
+        assert_eq!(reached.len(), 5);
+        assert!(reached.contains(&(1, 2)));
+        assert!(!reached.contains(&(2, 1)));
+    }
+
+    #[test]
+    fn sample_stairs_link_both_levels() {
+        let dungeon = Dungeon::embedded();
+        for (from, to) in [(0, 1), (1, 0)] {
+            let stairs = dungeon.levels[from]
+                .entities()
+                .iter()
+                .find(|entity| entity.destination.is_some())
+                .unwrap();
+            let (target, arrival) = dungeon.get_destination(from, stairs).unwrap();
+            let arrival = arrival.unwrap();
+            assert_eq!(target, to);
+            assert!(dungeon.levels[to].get_tile(arrival.x, arrival.z).is_some());
+        }
+    }
+
+    #[test]
+    fn sample_door_opens_the_corridor() {
+        let level = &Dungeon::embedded().levels[0];
+        assert!(level.get_door(7, 11, &Direction::Up).is_some());
+        let tile = level.get_tile(7, 11).unwrap();
+        assert!(tile.has_door(&Direction::Up) && !tile.has_wall(&Direction::Up));
+    }
+
+    #[test]
+    fn sample_enemy_and_cat_fields() {
+        let level = &Dungeon::embedded().levels[1];
+        let of_type = |is_type: fn(&EntityType) -> bool| {
+            level
+                .entities()
+                .iter()
+                .find(|entity| is_type(&entity.entity_type))
+                .unwrap()
+        };
+        let stats = of_type(|t| matches!(t, EntityType::Enemy)).stats.unwrap();
+        // empty fields fall back to the default enemy stats
+        assert_eq!((stats.hp, stats.attack, stats.defense), (8, 3, 1));
+        let cat = of_type(|t| matches!(t, EntityType::Cat));
+        assert_eq!(cat.behaviour, Some(CatBehaviour::Follow));
+        assert!(
+            matches!(&cat.dialogue, Some(DialogueSource::Asset(path)) if path.ends_with("cat.dialogue.json"))
+        );
+    }
+
+    #[test]
+    fn tiles_layer_without_a_tileset_is_skipped() {
+        let mut ldtk: Ldtk = serde_json::from_str(include_str!("../assets/level.ldtk")).unwrap();
+        for layer in ldtk.levels[0].layer_instances.iter_mut().flatten() {
+            if layer.identifier == "Tiles" {
+                layer.tileset_def_uid = None;
+            }
+        }
+        let (dungeon, warnings) = Dungeon::from_ldtk(&ldtk);
+        let messages: Vec<String> = warnings.iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec!["Level_0 (0, 0): Tiles layer without a tileset is skipped"]
+        );
+        assert!(!dungeon.levels[1].tiles().is_empty());
+    }
+
+    #[test]
+    fn tiles_are_placed_by_the_layer_grid_size() {
+        let mut ldtk: Ldtk = serde_json::from_str(include_str!("../assets/level.ldtk")).unwrap();
+        let cells = |dungeon: &Dungeon| -> Vec<(i32, i32)> {
+            dungeon.levels[0]
+                .tiles()
+                .iter()
+                .map(|tile| (tile.x, tile.z))
+                .collect()
+        };
+        let expected = cells(&Dungeon::from_ldtk(&ldtk).0);
+        // a layer with more cells than the grid size is wide
+        for layer in ldtk.levels[0].layer_instances.iter_mut().flatten() {
+            if layer.identifier == "Tiles" {
+                layer.c_wid = 20;
+            }
+        }
+        assert_eq!(cells(&Dungeon::from_ldtk(&ldtk).0), expected);
+    }
+
+    #[test]
+    fn doors_are_found_from_both_sides() {
+        let door = |iid: &str, x: i32, direction: Direction| Door {
+            iid: iid.to_owned(),
+            x,
+            z: 0,
+            direction,
+            locked: false,
+            key: None,
+        };
+        let mut level = level(&["..."]);
+        add_wall(&mut level, 0, 0, Direction::Right);
+        add_wall(&mut level, 1, 0, Direction::Left);
+        level.add_door(door("first", 0, Direction::Right));
+        level.add_door(door("second", 2, Direction::Left));
+        assert!(level.can_move(&at(0, 0), &Direction::Right));
+        assert!(
+            level
+                .get_door(1, 0, &Direction::Left)
+                .map(|(index, _)| index)
+                == Some(0)
+        );
+        assert!(
+            level
+                .get_door(1, 0, &Direction::Right)
+                .map(|(index, _)| index)
+                == Some(1)
+        );
+        assert!(level.get_door(1, 0, &Direction::Up).is_none());
+        assert!(level
+            .get_door_by_iid("second")
+            .is_some_and(|(_, door)| door.x == 2));
+        assert!(level.get_door_by_iid("third").is_none());
+    }
+
+    #[test]
+    fn large_level_indexes_match_the_lists() {
+        let stairs_down = Destination {
+            level: None,
+            position: None,
+        };
+        let level =
+            crate::generator::generate_level(1, "Large".to_owned(), 1, Some(stairs_down), 256, 256);
+        for tile in level.tiles() {
+            assert!(level
+                .get_tile(tile.x, tile.z)
+                .is_some_and(|found| (found.x, found.z) == (tile.x, tile.z)));
+        }
+        let cells = (0..256)
+            .flat_map(|z| (0..256).map(move |x| (x, z)))
+            .filter(|&(x, z)| level.get_tile(x, z).is_some())
+            .count();
+        assert_eq!(cells, level.tiles().len());
+        for entity in level.entities() {
+            assert!(level
+                .entities_at(entity.x, entity.z)
+                .any(|found| found.iid == entity.iid));
+            assert!(level.get_entity_by_iid(&entity.iid).is_some());
+        }
+    }
+
+    #[test]
+    fn get_tile_outside_the_level() {
+        let level = level(&["..", ".#"]);
+        assert!(level.get_tile(1, 0).is_some());
+        assert!(level.get_tile(1, 1).is_none());
+        assert!(level.get_tile(-1, 0).is_none());
+        assert!(level.get_tile(2, 0).is_none());
+    }
+
+    #[test]
+    fn get_entity_skips_other_entities_on_the_tile() {
+        let entity = |iid: &str, entity_type: EntityType| EventEntity {
+            iid: iid.to_owned(),
+            x: 1,
+            z: 0,
+            entity_type,
+            direction: Direction::Up,
+            message: None,
+            destination: None,
+            behaviour: None,
+            dialogue: None,
+            stats: None,
+            item: None,
+            fields: HashMap::new(),
+        };
+        let tiles = level(&[".."]).tiles().to_vec();
+        let level = Level::new(
+            "Test".to_owned(),
+            2,
+            1,
+            tiles,
+            vec![
+                EventEntity {
+                    message: Some("Meow".to_owned()),
+                    ..entity("cat", EntityType::Cat)
+                },
+                entity("light", EntityType::Light),
+                EventEntity {
+                    message: Some("Beware".to_owned()),
+                    ..entity("sign", EntityType::Custom("Sign".to_owned()))
+                },
+            ],
+            vec![],
+        );
+        assert_eq!(level.entities_at(1, 0).count(), 3);
+        assert_eq!(level.get_entity(1, 0).unwrap().iid, "sign");
+        assert!(level.get_entity(0, 0).is_none());
+    }
+}
@@ -0,0 +1,309 @@
+use crate::dungeon::EntityLink;
+use crate::ldtk::{EntityInstance, FieldInstance, GridPoint, ReferenceToAnEntityInstance};
+use bevy::prelude::*;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A custom field value of an LDtk entity, converted from its `__type`.
+#[derive(Clone, PartialEq)]
+pub enum FieldValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// `String`, `Multilines` and `FilePath` fields.
+    String(String),
+    /// The value of a `LocalEnum.*` or `ExternEnum.*` field.
+    Enum(String),
+    Color(Color),
+    /// A grid cell.
+    Point(i32, i32),
+    EntityRef(EntityLink),
+    Array(Vec<FieldValue>),
+}
+impl FieldValue {
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            FieldValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Int fields convert too, so a designer can type `2` for `2.0`.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            FieldValue::Float(value) => Some(*value),
+            FieldValue::Int(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            FieldValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The text of a string or enum field.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            FieldValue::String(value) | FieldValue::Enum(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_color(&self) -> Option<Color> {
+        match self {
+            FieldValue::Color(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_point(&self) -> Option<(i32, i32)> {
+        match self {
+            FieldValue::Point(x, z) => Some((*x, *z)),
+            _ => None,
+        }
+    }
+
+    /// The links of an EntityRef or `Array<EntityRef>` field.
+    pub fn links(&self) -> Vec<EntityLink> {
+        match self {
+            FieldValue::EntityRef(link) => vec![link.clone()],
+            FieldValue::Array(values) => values.iter().flat_map(|value| value.links()).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Converts the non-null custom fields of `entity`. `level_indices` maps LDtk
+/// level iids to level indices, for resolving EntityRef fields. Fields that
+/// can't be converted are left out, with the reason added to `warnings`.
+pub fn get_fields(
+    entity: &EntityInstance,
+    level_indices: &HashMap<&str, usize>,
+    warnings: &mut Vec<String>,
+) -> HashMap<String, FieldValue> {
+    entity
+        .field_instances
+        .iter()
+        .filter_map(|field| {
+            let value = parse_field(
+                field,
+                &field.field_instance_type,
+                field.value.as_ref()?,
+                level_indices,
+                warnings,
+            )?;
+            Some((field.identifier.clone(), value))
+        })
+        .collect()
+}
+
+fn parse_field(
+    field: &FieldInstance,
+    field_type: &str,
+    value: &Value,
+    level_indices: &HashMap<&str, usize>,
+    warnings: &mut Vec<String>,
+) -> Option<FieldValue> {
+    if let Some(item_type) = field_type
+        .strip_prefix("Array<")
+        .and_then(|rest| rest.strip_suffix('>'))
+    {
+        let values = value.as_array()?;
+        return Some(FieldValue::Array(
+            values
+                .iter()
+                .filter(|value| !value.is_null())
+                .filter_map(|value| parse_field(field, item_type, value, level_indices, warnings))
+                .collect(),
+        ));
+    }
+    let parsed = match field_type {
+        "Int" => value.as_i64().map(FieldValue::Int),
+        "Float" => value.as_f64().map(FieldValue::Float),
+        "Bool" => value.as_bool().map(FieldValue::Bool),
+        "String" | "Multilines" | "FilePath" => {
+            value.as_str().map(|s| FieldValue::String(s.to_owned()))
+        }
+        "Color" => value
+            .as_str()
+            .and_then(|s| Color::hex(s.trim_start_matches('#')).ok())
+            .map(FieldValue::Color),
+        "Point" => serde_json::from_value::<GridPoint>(value.clone())
+            .ok()
+            .map(|point| FieldValue::Point(point.cx as i32, point.cy as i32)),
+        "EntityRef" => match serde_json::from_value::<ReferenceToAnEntityInstance>(value.clone()) {
+            Ok(reference) => {
+                // the reference itself is fine, so this is its only warning
+                let Some(level) = level_indices.get(reference.level_iid.as_str()) else {
+                    warnings.push(format!(
+                        "{} links to an unknown level {}",
+                        field.identifier, reference.level_iid
+                    ));
+                    return None;
+                };
+                Some(FieldValue::EntityRef(EntityLink {
+                    level: *level,
+                    iid: reference.entity_iid,
+                }))
+            }
+            Err(_) => None,
+        },
+        _ if field_type.starts_with("LocalEnum.") || field_type.starts_with("ExternEnum.") => {
+            value.as_str().map(|s| FieldValue::Enum(s.to_owned()))
+        }
+        _ => {
+            warnings.push(format!(
+                "unsupported field type {} of {}",
+                field_type, field.identifier
+            ));
+            return None;
+        }
+    };
+    if parsed.is_none() {
+        warnings.push(format!(
+            "field {} is not a valid {}",
+            field.identifier, field_type
+        ));
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// An entity with one field of each `(type, value)` pair, named after its
+    /// position in the list.
+    fn entity(fields: &[(&str, Value)]) -> EntityInstance {
+        let fields: Vec<Value> = fields
+            .iter()
+            .enumerate()
+            .map(|(index, (field_type, value))| {
+                json!({
+                    "__identifier": index.to_string(),
+                    "__tile": null,
+                    "__type": field_type,
+                    "__value": value,
+                    "defUid": 0,
+                    "realEditorValues": [],
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({
+            "__grid": [0, 0],
+            "__identifier": "Test",
+            "__pivot": [0, 0],
+            "__smartColor": "#ffffff",
+            "__tags": [],
+            "__tile": null,
+            "defUid": 0,
+            "fieldInstances": fields,
+            "height": 16,
+            "iid": "test",
+            "px": [0, 0],
+            "width": 16,
+        }))
+        .unwrap()
+    }
+
+    /// The converted value of a single field, and the warnings it caused.
+    fn parse(field_type: &str, value: Value) -> (Option<FieldValue>, Vec<String>) {
+        let level_indices = HashMap::from([("level", 1)]);
+        let mut warnings = vec![];
+        let mut fields = get_fields(
+            &entity(&[(field_type, value)]),
+            &level_indices,
+            &mut warnings,
+        );
+        (fields.remove("0"), warnings)
+    }
+
+    fn reference(level_iid: &str) -> Value {
+        json!({
+            "entityIid": "target",
+            "layerIid": "layer",
+            "levelIid": level_iid,
+            "worldIid": "world",
+        })
+    }
+
+    #[test]
+    fn scalar_fields() {
+        let value = |field_type: &str, value: Value| parse(field_type, value).0.unwrap();
+        assert!(value("Int", json!(3)).as_i64() == Some(3));
+        assert!(value("Float", json!(0.5)).as_f64() == Some(0.5));
+        // Int fields read as floats too
+        assert!(value("Int", json!(2)).as_f64() == Some(2.0));
+        assert!(value("Bool", json!(true)).as_bool() == Some(true));
+        for field_type in ["String", "Multilines", "FilePath"] {
+            assert!(value(field_type, json!("text")) == FieldValue::String("text".to_owned()));
+        }
+        assert!(value("Color", json!("#ff0000")).as_color() == Some(Color::rgb(1.0, 0.0, 0.0)));
+        assert!(value("Point", json!({ "cx": 2, "cy": 3 })).as_point() == Some((2, 3)));
+        let direction = value("LocalEnum.Direction", json!("Up"));
+        assert!(direction == FieldValue::Enum("Up".to_owned()));
+        assert!(direction.as_str() == Some("Up"));
+        assert!(value("ExternEnum.Kind", json!("Big")).as_str() == Some("Big"));
+    }
+
+    #[test]
+    fn entity_ref_fields() {
+        let (link, warnings) = parse("EntityRef", reference("level"));
+        let links = link.unwrap().links();
+        assert!(links.len() == 1 && links[0].level == 1 && links[0].iid == "target");
+        assert!(warnings.is_empty());
+
+        let (link, warnings) = parse("EntityRef", reference("elsewhere"));
+        assert!(link.is_none());
+        assert_eq!(warnings, vec!["0 links to an unknown level elsewhere"]);
+    }
+
+    #[test]
+    fn array_fields_skip_nulls() {
+        let (values, _) = parse("Array<Int>", json!([1, null, 2]));
+        assert!(
+            values
+                == Some(FieldValue::Array(vec![
+                    FieldValue::Int(1),
+                    FieldValue::Int(2)
+                ]))
+        );
+
+        let (links, _) = parse(
+            "Array<EntityRef>",
+            json!([reference("level"), reference("level")]),
+        );
+        assert_eq!(links.unwrap().links().len(), 2);
+    }
+
+    #[test]
+    fn null_fields_are_left_out() {
+        let mut warnings = vec![];
+        let fields = get_fields(
+            &entity(&[("Int", Value::Null), ("String", json!("set"))]),
+            &HashMap::new(),
+            &mut warnings,
+        );
+        assert!(!fields.contains_key("0"));
+        assert!(fields.contains_key("1"));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn unusable_fields_warn() {
+        let (value, warnings) = parse("Tile", json!({ "tilesetUid": 1 }));
+        assert!(value.is_none());
+        assert_eq!(warnings, vec!["unsupported field type Tile of 0"]);
+
+        let (value, warnings) = parse("Int", json!("three"));
+        assert!(value.is_none());
+        assert_eq!(warnings, vec!["field 0 is not a valid Int"]);
+
+        let (value, _) = parse("Color", json!("not a color"));
+        assert!(value.is_none());
+    }
+}
@@ -0,0 +1,385 @@
+use crate::cat::CatBehaviour;
+use crate::combat::Stats;
+use crate::dialogue::DialogueSource;
+use crate::dungeon::{Destination, Dungeon, EntityType, EventEntity, Level, Terrain, Tile};
+use crate::position::{Direction, Position};
+use ::serde::{Deserialize, Serialize};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+const LEVEL_WIDTH: i32 = 24;
+const LEVEL_LENGTH: i32 = 24;
+const FLOORS: usize = 3;
+const ROOM_ATTEMPTS: usize = 40;
+/// Smallest and largest room side, exclusive.
+const ROOM_SIZE: (i32, i32) = (3, 7);
+const CATS_PER_LEVEL: usize = 3;
+const ENEMIES_PER_LEVEL: usize = 2;
+const ITEMS_PER_LEVEL: usize = 2;
+const ITEMS: [&str; 4] = ["potion", "potion", "sword", "shield"];
+const CAT_MESSAGES: [&str; 4] = ["Meow", "Mew", "Purr", "Nya"];
+const CAT_DIALOGUE: &str = "dialogue/cat.dialogue.json";
+const CAT_BEHAVIOURS: [CatBehaviour; 3] = [
+    CatBehaviour::Wander,
+    CatBehaviour::Follow,
+    CatBehaviour::Flee,
+];
+
+/// Seed of the generated run, `None` while playing the LDtk levels.
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
+pub struct DungeonSeed(pub Option<u64>);
+
+/// SplitMix64, so that a seed rebuilds the same run on every platform.
+pub struct Rng(pub u64);
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Random number in `min..max`.
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        min + (self.next_u64() % (max - min) as u64) as i32
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Room {
+    x: i32,
+    z: i32,
+    width: i32,
+    length: i32,
+}
+impl Room {
+    fn center(&self) -> (i32, i32) {
+        (self.x + self.width / 2, self.z + self.length / 2)
+    }
+
+    fn intersects(&self, other: &Room) -> bool {
+        // keep at least one cell between rooms
+        self.x - 1 < other.x + other.width
+            && other.x - 1 < self.x + self.width
+            && self.z - 1 < other.z + other.length
+            && other.z - 1 < self.z + self.length
+    }
+}
+
+impl Dungeon {
+    pub fn generate(seed: u64) -> Self {
+        // built from the bottom up, so the stairs down can lead beside the
+        // stairs up of the floor below
+        let mut levels: Vec<Level> = vec![];
+        let mut below: Option<Destination> = None;
+        for floor in (0..FLOORS).rev() {
+            let level = generate_level(
+                seed.wrapping_add(floor as u64),
+                format!("Generated_{}", floor),
+                floor,
+                below,
+                LEVEL_WIDTH,
+                LEVEL_LENGTH,
+            );
+            below = Some(Destination {
+                level: None,
+                position: beside_stairs_up(&level),
+            });
+            levels.push(level);
+        }
+        levels.reverse();
+        Dungeon { levels }
+    }
+}
+
+/// A free cell next to the stairs up, facing away from them.
+fn beside_stairs_up(level: &Level) -> Option<Position> {
+    let stairs = level
+        .entities()
+        .iter()
+        .find(|entity| matches!(entity.entity_type, EntityType::StairsUp))?;
+    let neighbours = level.neighbours(&Position {
+        direction: stairs.direction.clone(),
+        x: stairs.x,
+        z: stairs.z,
+    });
+    neighbours
+        .iter()
+        .find(|cell| level.entities_at(cell.x, cell.z).next().is_none())
+        .or(neighbours.first())
+        .cloned()
+}
+
+fn random_direction(rng: &mut Rng) -> Direction {
+    match rng.range(0, 4) {
+        0 => Direction::Right,
+        1 => Direction::Up,
+        2 => Direction::Left,
+        _ => Direction::Down,
+    }
+}
+
+/// Builds a rooms-and-corridors level. Walls are derived from the carved cells,
+/// so both sides of every edge always agree. Bigger levels get more rooms.
+/// `stairs_down` is where the stairs down lead, `None` on the bottom floor.
+///
+/// Panics if the level is too small to hold the smallest room.
+pub fn generate_level(
+    seed: u64,
+    identifier: String,
+    depth: usize,
+    stairs_down: Option<Destination>,
+    level_width: i32,
+    level_length: i32,
+) -> Level {
+    assert!(
+        level_width > ROOM_SIZE.0 && level_length > ROOM_SIZE.0,
+        "a {}x{} level is too small for a room",
+        level_width,
+        level_length
+    );
+    let mut rng = Rng(seed);
+
+    // the first attempt always fits, so there is at least one room
+    let mut rooms: Vec<Room> = vec![];
+    let attempts = (ROOM_ATTEMPTS * (level_width * level_length) as usize
+        / (LEVEL_WIDTH * LEVEL_LENGTH) as usize)
+        .max(1);
+    for _ in 0..attempts {
+        // rooms keep a cell free on the far sides, as on full-size levels
+        let width = rng.range(ROOM_SIZE.0, ROOM_SIZE.1).min(level_width - 1);
+        let length = rng.range(ROOM_SIZE.0, ROOM_SIZE.1).min(level_length - 1);
+        let room = Room {
+            x: rng.range(0, level_width - width),
+            z: rng.range(0, level_length - length),
+            width,
+            length,
+        };
+        if rooms.iter().all(|other| !room.intersects(other)) {
+            rooms.push(room);
+        }
+    }
+
+    let mut floor: HashSet<(i32, i32)> = HashSet::new();
+    for room in rooms.iter() {
+        for x in room.x..room.x + room.width {
+            for z in room.z..room.z + room.length {
+                floor.insert((x, z));
+            }
+        }
+    }
+    for pair in rooms.windows(2) {
+        let (x0, z0) = pair[0].center();
+        let (x1, z1) = pair[1].center();
+        // L-shaped corridor, randomly horizontal or vertical first
+        let corner = if rng.range(0, 2) == 0 {
+            (x1, z0)
+        } else {
+            (x0, z1)
+        };
+        for (from, to) in [((x0, z0), corner), (corner, (x1, z1))] {
+            for x in from.0.min(to.0)..=from.0.max(to.0) {
+                for z in from.1.min(to.1)..=from.1.max(to.1) {
+                    floor.insert((x, z));
+                }
+            }
+        }
+    }
+
+    let mut tiles: Vec<Tile> = floor
+        .iter()
+        .map(|&(x, z)| Tile {
+            x,
+            z,
+            walls: [
+                (Direction::Right, (x + 1, z)),
+                (Direction::Up, (x, z - 1)),
+                (Direction::Left, (x - 1, z)),
+                (Direction::Down, (x, z + 1)),
+            ]
+            .into_iter()
+            .filter(|(_, neighbour)| !floor.contains(neighbour))
+            .map(|(direction, _)| direction)
+            .collect(),
+            doors: HashSet::new(),
+            terrain: Terrain::Floor,
+        })
+        .collect();
+    tiles.sort_by_key(|tile| (tile.z, tile.x));
+
+    let mut entities: Vec<EventEntity> = vec![];
+    let event_entity =
+        |entity_type: EntityType, (x, z): (i32, i32), direction: Direction| EventEntity {
+            iid: String::new(),
+            x,
+            z,
+            entity_type,
+            direction,
+            message: None,
+            destination: None,
+            behaviour: None,
+            dialogue: None,
+            stats: None,
+            item: None,
+            fields: HashMap::new(),
+        };
+    let start = rooms[0].center();
+    entities.push(event_entity(
+        EntityType::PlayerStart,
+        start,
+        random_direction(&mut rng),
+    ));
+    if let Some(destination) = stairs_down {
+        // a lone room keeps them in the corner away from the stairs up
+        let cell = match rooms[..] {
+            [room] => (room.x + room.width - 1, room.z + room.length - 1),
+            _ => rooms[rooms.len() - 1].center(),
+        };
+        let mut stairs = event_entity(EntityType::StairsDown, cell, Direction::Up);
+        stairs.destination = Some(destination);
+        entities.push(stairs);
+    }
+    // the way back up is in the starting room, away from the stairs down
+    if depth > 0 {
+        let room = rooms[0];
+        let mut stairs = event_entity(EntityType::StairsUp, (room.x, room.z), Direction::Up);
+        stairs.destination = Some(Destination {
+            level: None,
+            position: None,
+        });
+        entities.push(stairs);
+    }
+    for index in 0..CATS_PER_LEVEL {
+        let room = rooms[rng.range(0, rooms.len() as i32) as usize];
+        let cell = (
+            rng.range(room.x, room.x + room.width),
+            rng.range(room.z, room.z + room.length),
+        );
+        if entities.iter().any(|e| (e.x, e.z) == cell) {
+            continue;
+        }
+        let mut cat = event_entity(EntityType::Cat, cell, random_direction(&mut rng));
+        cat.message =
+            Some(CAT_MESSAGES[rng.range(0, CAT_MESSAGES.len() as i32) as usize].to_owned());
+        cat.behaviour = Some(CAT_BEHAVIOURS[rng.range(0, CAT_BEHAVIOURS.len() as i32) as usize]);
+        if index == 0 {
+            cat.dialogue = Some(DialogueSource::Asset(CAT_DIALOGUE.to_owned()));
+        }
+        entities.push(cat);
+    }
+    for _ in 0..ITEMS_PER_LEVEL {
+        let room = rooms[rng.range(0, rooms.len() as i32) as usize];
+        let cell = (
+            rng.range(room.x, room.x + room.width),
+            rng.range(room.z, room.z + room.length),
+        );
+        if entities.iter().any(|e| (e.x, e.z) == cell) {
+            continue;
+        }
+        let mut item = event_entity(EntityType::Item, cell, Direction::Up);
+        item.item = Some(ITEMS[rng.range(0, ITEMS.len() as i32) as usize].to_owned());
+        entities.push(item);
+    }
+    // enemies get tougher on every floor and stay out of the starting room
+    for _ in 0..ENEMIES_PER_LEVEL {
+        if rooms.len() < 2 {
+            break;
+        }
+        let room = rooms[rng.range(1, rooms.len() as i32) as usize];
+        let cell = (
+            rng.range(room.x, room.x + room.width),
+            rng.range(room.z, room.z + room.length),
+        );
+        if entities.iter().any(|e| (e.x, e.z) == cell) {
+            continue;
+        }
+        let mut enemy = event_entity(EntityType::Enemy, cell, random_direction(&mut rng));
+        let depth = depth as i32;
+        enemy.stats = Some(Stats::new(6 + depth * 3, 2 + depth, depth));
+        entities.push(enemy);
+    }
+    // levels are regenerated from the seed, so the index is stable enough
+    for (index, entity) in entities.iter_mut().enumerate() {
+        entity.iid = format!("{}-{}", identifier, index);
+    }
+
+    Level::new(
+        identifier,
+        level_width,
+        level_length,
+        tiles,
+        entities,
+        vec![],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell_of(level: &Level, entity_type: fn(&EntityType) -> bool) -> Option<(i32, i32)> {
+        level
+            .entities()
+            .iter()
+            .find(|entity| entity_type(&entity.entity_type))
+            .map(|entity| (entity.x, entity.z))
+    }
+
+    #[test]
+    fn stairs_up_below_the_first_floor() {
+        let dungeon = Dungeon::generate(7);
+        let up = |level| cell_of(level, |t| matches!(t, EntityType::StairsUp));
+        let down = |level| cell_of(level, |t| matches!(t, EntityType::StairsDown));
+        assert!(up(&dungeon.levels[0]).is_none());
+        for (depth, level) in dungeon.levels.iter().enumerate().skip(1) {
+            let cell = up(level).unwrap();
+            assert!(level.get_tile(cell.0, cell.1).is_some());
+            assert!(Some(cell) != down(level));
+            assert!(dungeon
+                .get_destination(depth, level.get_entity(cell.0, cell.1).unwrap())
+                .is_some_and(|(target, _)| target == depth - 1));
+        }
+    }
+
+    #[test]
+    fn stairs_down_lead_beside_the_stairs_up() {
+        let dungeon = Dungeon::generate(7);
+        let (last, upper) = dungeon.levels.split_last().unwrap();
+        assert!(cell_of(last, |t| matches!(t, EntityType::StairsDown)).is_none());
+        for (depth, level) in upper.iter().enumerate() {
+            let (x, z) = cell_of(level, |t| matches!(t, EntityType::StairsDown)).unwrap();
+            let (target, arrival) = dungeon
+                .get_destination(depth, level.get_entity(x, z).unwrap())
+                .unwrap();
+            let arrival = arrival.unwrap();
+            let below = &dungeon.levels[target];
+            let up = cell_of(below, |t| matches!(t, EntityType::StairsUp)).unwrap();
+            assert_eq!(target, depth + 1);
+            assert_eq!((arrival.x - up.0).abs() + (arrival.z - up.1).abs(), 1);
+            assert!(below.get_tile(arrival.x, arrival.z).is_some());
+        }
+    }
+
+    #[test]
+    fn smallest_level_has_a_room() {
+        let stairs_down = Destination {
+            level: None,
+            position: None,
+        };
+        let level = generate_level(3, "Small".to_owned(), 1, Some(stairs_down), 4, 4);
+        assert!(!level.tiles().is_empty());
+        let cells = [
+            cell_of(&level, |t| matches!(t, EntityType::PlayerStart)).unwrap(),
+            cell_of(&level, |t| matches!(t, EntityType::StairsUp)).unwrap(),
+            cell_of(&level, |t| matches!(t, EntityType::StairsDown)).unwrap(),
+        ];
+        assert!(cells[0] != cells[1] && cells[1] != cells[2] && cells[0] != cells[2]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn too_small_for_a_room() {
+        generate_level(3, "Tiny".to_owned(), 0, None, 3, 10);
+    }
+}
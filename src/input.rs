@@ -0,0 +1,490 @@
+use crate::{UiFont, NORMAL_BUTTON};
+use ::serde::{Deserialize, Serialize};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+const STICK_THRESHOLD: f32 = 0.6;
+
+const GAMEPAD_BUTTONS: [(GamepadButtonType, Action); 11] = [
+    (GamepadButtonType::DPadUp, Action::Forward),
+    (GamepadButtonType::DPadDown, Action::Backward),
+    (GamepadButtonType::DPadLeft, Action::TurnLeft),
+    (GamepadButtonType::DPadRight, Action::TurnRight),
+    (GamepadButtonType::LeftTrigger, Action::StrafeLeft),
+    (GamepadButtonType::RightTrigger, Action::StrafeRight),
+    (GamepadButtonType::West, Action::TurnAround),
+    (GamepadButtonType::South, Action::Interact),
+    (GamepadButtonType::North, Action::Map),
+    (GamepadButtonType::Select, Action::Inventory),
+    (GamepadButtonType::East, Action::Explore),
+];
+
+/// Stick axes with the actions for their negative and positive directions.
+const GAMEPAD_AXES: [(GamepadAxisType, Action, Action); 3] = [
+    (
+        GamepadAxisType::LeftStickY,
+        Action::Backward,
+        Action::Forward,
+    ),
+    (
+        GamepadAxisType::LeftStickX,
+        Action::TurnLeft,
+        Action::TurnRight,
+    ),
+    (
+        GamepadAxisType::RightStickX,
+        Action::StrafeLeft,
+        Action::StrafeRight,
+    ),
+];
+
+/// Touch D-pad layout, row by row.
+const TOUCH_BUTTONS: [(Action, &str); 9] = [
+    (Action::StrafeLeft, "Q"),
+    (Action::Forward, "^"),
+    (Action::StrafeRight, "E"),
+    (Action::TurnLeft, "<"),
+    (Action::Interact, "o"),
+    (Action::TurnRight, ">"),
+    (Action::TurnAround, "U"),
+    (Action::Backward, "v"),
+    (Action::Map, "M"),
+];
+
+pub const ACTIONS: [Action; 11] = [
+    Action::Forward,
+    Action::Backward,
+    Action::StrafeLeft,
+    Action::StrafeRight,
+    Action::TurnLeft,
+    Action::TurnRight,
+    Action::TurnAround,
+    Action::Interact,
+    Action::Map,
+    Action::Inventory,
+    Action::Explore,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Action {
+    Forward,
+    Backward,
+    StrafeLeft,
+    StrafeRight,
+    TurnLeft,
+    TurnRight,
+    TurnAround,
+    Interact,
+    Map,
+    Inventory,
+    Explore,
+}
+impl Action {
+    fn label(&self) -> &'static str {
+        match self {
+            Action::Forward => "Forward",
+            Action::Backward => "Back",
+            Action::StrafeLeft => "Strafe L",
+            Action::StrafeRight => "Strafe R",
+            Action::TurnLeft => "Turn L",
+            Action::TurnRight => "Turn R",
+            Action::TurnAround => "Turn 180",
+            Action::Interact => "Interact",
+            Action::Map => "Map",
+            Action::Inventory => "Items",
+            Action::Explore => "Explore",
+        }
+    }
+
+    /// Whether the action moves or turns the player.
+    pub fn is_move(&self) -> bool {
+        !matches!(
+            self,
+            Action::Interact | Action::Map | Action::Inventory | Action::Explore
+        )
+    }
+
+    /// Whether the action steps onto another tile.
+    pub fn is_step(&self) -> bool {
+        matches!(
+            self,
+            Action::Forward | Action::Backward | Action::StrafeLeft | Action::StrafeRight
+        )
+    }
+}
+
+/// Sent once for every action the player triggers, whatever the input device.
+#[derive(Event, Clone, Copy)]
+pub struct ActionEvent(pub Action);
+
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub struct KeyBindings(pub HashMap<Action, Vec<KeyCode>>);
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings(HashMap::from([
+            (Action::Forward, vec![KeyCode::W, KeyCode::Up]),
+            (Action::Backward, vec![KeyCode::S, KeyCode::Down]),
+            (Action::StrafeLeft, vec![KeyCode::Q]),
+            (Action::StrafeRight, vec![KeyCode::E]),
+            (Action::TurnLeft, vec![KeyCode::A, KeyCode::Left]),
+            (Action::TurnRight, vec![KeyCode::D, KeyCode::Right]),
+            (Action::TurnAround, vec![KeyCode::X]),
+            (Action::Interact, vec![KeyCode::Space]),
+            (Action::Map, vec![KeyCode::M]),
+            (Action::Inventory, vec![KeyCode::I]),
+            (Action::Explore, vec![KeyCode::O]),
+        ]))
+    }
+}
+impl KeyBindings {
+    /// Adds the default keys of actions missing from bindings saved before they existed.
+    pub fn fill_missing(&mut self) {
+        for (action, keys) in KeyBindings::default().0 {
+            self.0.entry(action).or_insert(keys);
+        }
+    }
+
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        self.0
+            .get(&action)
+            .map(|keys| keys.as_slice())
+            .unwrap_or(&[])
+    }
+}
+
+/// How many moves may wait while the player is still walking, and whether
+/// holding a movement input keeps stepping.
+#[derive(Resource)]
+pub struct InputBuffer {
+    pub depth: usize,
+    pub auto_repeat: bool,
+}
+impl Default for InputBuffer {
+    fn default() -> Self {
+        InputBuffer {
+            depth: 2,
+            auto_repeat: true,
+        }
+    }
+}
+
+/// Actions whose key, button, stick or touch button is currently held down.
+#[derive(Resource, Default)]
+pub struct HeldActions(pub HashSet<Action>);
+
+/// The action waiting for a key press to be rebound to.
+#[derive(Resource, Default)]
+pub struct Rebinding(pub Option<Action>);
+
+#[derive(Component)]
+pub struct KeyConfigButton;
+
+#[derive(Component)]
+pub struct KeyConfigPanel;
+
+#[derive(Component)]
+pub struct BindingButton(pub Action);
+
+#[derive(Component)]
+pub struct BindingText(pub Action);
+
+#[derive(Component)]
+pub struct TouchPad;
+
+#[derive(Component)]
+pub struct TouchButton(pub Action);
+
+pub fn read_keyboard(
+    keys: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    rebinding: Res<Rebinding>,
+    mut action_events: EventWriter<ActionEvent>,
+) {
+    if rebinding.0.is_some() {
+        return;
+    }
+    for action in ACTIONS {
+        if keys.any_just_pressed(key_bindings.keys(action).iter().copied()) {
+            action_events.send(ActionEvent(action));
+        }
+    }
+}
+
+/// Sends an action when a stick crosses the threshold, not on every frame it is held.
+pub fn read_gamepad(
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut stick_states: Local<HashMap<GamepadAxis, i8>>,
+    mut action_events: EventWriter<ActionEvent>,
+) {
+    for gamepad in gamepads.iter() {
+        for (button_type, action) in GAMEPAD_BUTTONS {
+            if buttons.just_pressed(GamepadButton::new(gamepad, button_type)) {
+                action_events.send(ActionEvent(action));
+            }
+        }
+        for (axis_type, negative, positive) in GAMEPAD_AXES {
+            let axis = GamepadAxis::new(gamepad, axis_type);
+            let value = axes.get(axis).unwrap_or(0.0);
+            let state = if value > STICK_THRESHOLD {
+                1
+            } else if value < -STICK_THRESHOLD {
+                -1
+            } else {
+                0
+            };
+            let previous = stick_states.insert(axis, state).unwrap_or(0);
+            if state != previous {
+                match state {
+                    1 => action_events.send(ActionEvent(positive)),
+                    -1 => action_events.send(ActionEvent(negative)),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_held_actions(
+    keys: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    rebinding: Res<Rebinding>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    touch_query: Query<(&Interaction, &TouchButton)>,
+    mut held: ResMut<HeldActions>,
+) {
+    held.0.clear();
+    if rebinding.0.is_some() {
+        return;
+    }
+    for action in ACTIONS {
+        if keys.any_pressed(key_bindings.keys(action).iter().copied()) {
+            held.0.insert(action);
+        }
+    }
+    for gamepad in gamepads.iter() {
+        for (button_type, action) in GAMEPAD_BUTTONS {
+            if buttons.pressed(GamepadButton::new(gamepad, button_type)) {
+                held.0.insert(action);
+            }
+        }
+        for (axis_type, negative, positive) in GAMEPAD_AXES {
+            let value = axes
+                .get(GamepadAxis::new(gamepad, axis_type))
+                .unwrap_or(0.0);
+            if value > STICK_THRESHOLD {
+                held.0.insert(positive);
+            } else if value < -STICK_THRESHOLD {
+                held.0.insert(negative);
+            }
+        }
+    }
+    for (interaction, touch_button) in &touch_query {
+        if *interaction == Interaction::Pressed {
+            held.0.insert(touch_button.0);
+        }
+    }
+}
+
+pub fn read_touch_buttons(
+    interaction_query: Query<(&Interaction, &TouchButton), Changed<Interaction>>,
+    mut action_events: EventWriter<ActionEvent>,
+) {
+    for (interaction, touch_button) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            action_events.send(ActionEvent(touch_button.0));
+        }
+    }
+}
+
+pub fn setup_touch_pad(mut commands: Commands, ui_font: Res<UiFont>) {
+    let text_style = TextStyle {
+        font: ui_font.0.clone(),
+        font_size: 12.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn((
+            TouchPad,
+            NodeBundle {
+                z_index: ZIndex::Global(105),
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(2.0),
+                    bottom: Val::Px(2.0),
+                    width: Val::Px(66.0),
+                    flex_wrap: FlexWrap::Wrap,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            for (action, label) in TOUCH_BUTTONS {
+                parent
+                    .spawn((
+                        TouchButton(action),
+                        ButtonBundle {
+                            style: Style {
+                                width: Val::Px(20.0),
+                                height: Val::Px(20.0),
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                margin: UiRect::all(Val::Px(1.0)),
+                                ..default()
+                            },
+                            background_color: NORMAL_BUTTON.into(),
+                            ..default()
+                        },
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section(label, text_style.clone()));
+                    });
+            }
+        });
+}
+
+/// The touch D-pad stays hidden until the screen is touched for the first time.
+pub fn show_touch_pad(touches: Res<Touches>, mut query: Query<&mut Style, With<TouchPad>>) {
+    if !touches.any_just_pressed() {
+        return;
+    }
+    let mut style = query.single_mut();
+    if style.display == Display::None {
+        style.display = Display::Flex;
+    }
+}
+
+pub fn setup_key_config(mut commands: Commands, ui_font: Res<UiFont>) {
+    let text_style = TextStyle {
+        font: ui_font.0.clone(),
+        font_size: 12.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn((
+            KeyConfigPanel,
+            NodeBundle {
+                z_index: ZIndex::Global(130),
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(2.0),
+                    top: Val::Px(34.0),
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(2.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.85).into(),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            for action in ACTIONS {
+                parent
+                    .spawn((
+                        BindingButton(action),
+                        ButtonBundle {
+                            style: Style {
+                                width: Val::Px(140.0),
+                                height: Val::Px(16.0),
+                                align_items: AlignItems::Center,
+                                padding: UiRect::horizontal(Val::Px(2.0)),
+                                margin: UiRect::all(Val::Px(1.0)),
+                                ..default()
+                            },
+                            background_color: NORMAL_BUTTON.into(),
+                            ..default()
+                        },
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            BindingText(action),
+                            TextBundle::from_section("", text_style.clone()),
+                        ));
+                    });
+            }
+        });
+}
+
+pub fn interact_key_config_button(
+    mut rebinding: ResMut<Rebinding>,
+    mut panel_query: Query<&mut Style, With<KeyConfigPanel>>,
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<KeyConfigButton>)>,
+) {
+    for interaction in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let mut style = panel_query.single_mut();
+        style.display = if style.display == Display::None {
+            Display::Flex
+        } else {
+            Display::None
+        };
+        rebinding.0 = None;
+    }
+}
+
+pub fn interact_binding_button(
+    mut rebinding: ResMut<Rebinding>,
+    interaction_query: Query<(&Interaction, &BindingButton), Changed<Interaction>>,
+) {
+    for (interaction, binding_button) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            rebinding.0 = Some(binding_button.0);
+        }
+    }
+}
+
+/// Binds the next pressed key to the action being rebound. Escape cancels.
+pub fn capture_binding(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut key_bindings: ResMut<KeyBindings>,
+) {
+    let Some(action) = rebinding.0 else {
+        return;
+    };
+    let Some(key) = keys.get_just_pressed().next().copied() else {
+        return;
+    };
+    keys.clear_just_pressed(key);
+    rebinding.0 = None;
+    if key == KeyCode::Escape {
+        return;
+    }
+    // a key drives a single action
+    for keys in key_bindings.0.values_mut() {
+        keys.retain(|bound| *bound != key);
+    }
+    key_bindings.0.insert(action, vec![key]);
+}
+
+pub fn update_binding_text(
+    key_bindings: Res<KeyBindings>,
+    rebinding: Res<Rebinding>,
+    mut query: Query<(&mut Text, &BindingText)>,
+) {
+    if !key_bindings.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+    for (mut text, binding_text) in &mut query {
+        let keys = if rebinding.0 == Some(binding_text.0) {
+            "press a key".to_owned()
+        } else {
+            key_bindings
+                .keys(binding_text.0)
+                .iter()
+                .map(|key| format!("{:?}", key))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        text.sections[0].value = format!("{}: {}", binding_text.0.label(), keys);
+    }
+}
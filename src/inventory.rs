@@ -0,0 +1,560 @@
+use crate::combat::Stats;
+use crate::dungeon::{Dungeon, DungeonLevel, EntityType};
+use crate::input::{Action, ActionEvent};
+use crate::player::{Player, PlayerLock};
+use crate::position::{Direction, Position};
+use crate::stats::PlayerStats;
+use crate::world::{EntityIid, WorldState};
+use crate::{MessageEvent, SpawnDungeonEvent, UiFont, NORMAL_BUTTON};
+use ::serde::{Deserialize, Serialize};
+use bevy::prelude::*;
+
+const SELECTED_COLOR: Color = Color::rgb(1.0, 1.0, 0.4);
+const EQUIPPED_COLOR: Color = Color::rgb(0.35, 0.75, 0.35);
+
+const INVENTORY_COMMANDS: [InventoryCommand; 3] = [
+    InventoryCommand::Use,
+    InventoryCommand::Equip,
+    InventoryCommand::Drop,
+];
+
+pub enum ItemKind {
+    Heal(i32),
+    Weapon(i32),
+    Armor(i32),
+    /// Opens the doors whose `Key` field is the item id.
+    Key,
+}
+
+/// Item ids without an entry are keys named after their id.
+pub fn item_kind(id: &str) -> ItemKind {
+    match id {
+        "potion" => ItemKind::Heal(10),
+        "sword" => ItemKind::Weapon(2),
+        "shield" => ItemKind::Armor(1),
+        _ => ItemKind::Key,
+    }
+}
+
+pub fn item_name(id: &str) -> &str {
+    match id {
+        "potion" => "Potion",
+        "sword" => "Sword",
+        "shield" => "Shield",
+        "cat_key" => "Cat key",
+        _ => id,
+    }
+}
+
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
+pub struct Inventory {
+    pub items: Vec<String>,
+    pub weapon: Option<String>,
+    pub armor: Option<String>,
+}
+impl Inventory {
+    pub fn has(&self, id: &str) -> bool {
+        self.items.iter().any(|item| item == id)
+    }
+
+    pub fn is_equipped(&self, id: &str) -> bool {
+        self.weapon.as_deref() == Some(id) || self.armor.as_deref() == Some(id)
+    }
+
+    /// `stats` with the bonuses of the equipped weapon and armor.
+    pub fn equipped_stats(&self, stats: &Stats) -> Stats {
+        let mut equipped = *stats;
+        for id in self.weapon.iter().chain(self.armor.iter()) {
+            match item_kind(id) {
+                ItemKind::Weapon(attack) => equipped.attack += attack,
+                ItemKind::Armor(defense) => equipped.defense += defense,
+                _ => (),
+            }
+        }
+        equipped
+    }
+
+    fn remove(&mut self, index: usize) -> String {
+        let id = self.items.remove(index);
+        if !self.has(&id) {
+            if self.weapon.as_ref() == Some(&id) {
+                self.weapon = None;
+            }
+            if self.armor.as_ref() == Some(&id) {
+                self.armor = None;
+            }
+        }
+        id
+    }
+}
+
+#[derive(Component)]
+pub struct ItemPickup(pub String);
+
+#[derive(Resource)]
+pub struct ItemAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+impl FromWorld for ItemAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mesh = world
+            .resource_mut::<Assets<Mesh>>()
+            .add(shape::Cube { size: 0.15 }.into());
+        let material = world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .add(StandardMaterial {
+                base_color: Color::rgb(0.9, 0.75, 0.2),
+                unlit: false,
+                ..default()
+            });
+        ItemAssets { mesh, material }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum InventoryCommand {
+    Use,
+    Equip,
+    Drop,
+}
+impl InventoryCommand {
+    fn label(&self) -> &'static str {
+        match self {
+            InventoryCommand::Use => "Use",
+            InventoryCommand::Equip => "Equip",
+            InventoryCommand::Drop => "Drop",
+        }
+    }
+}
+
+/// The open inventory screen with its selected item and command.
+#[derive(Resource, Default)]
+pub struct InventoryScreen(pub Option<(usize, usize)>);
+
+#[derive(Component)]
+pub struct InventoryPanel;
+
+#[derive(Component)]
+pub struct InventoryList;
+
+#[derive(Component)]
+pub struct InventoryItemButton(pub usize);
+
+/// The label of the item at the index, recoloured as the selection moves.
+#[derive(Component)]
+pub struct InventoryItemText(pub usize);
+
+#[derive(Component)]
+pub struct InventoryCommandButton(pub InventoryCommand);
+
+#[derive(Component)]
+pub struct InventoryCommandText(pub InventoryCommand);
+
+fn spawn_item(
+    commands: &mut Commands,
+    item_assets: &ItemAssets,
+    item: String,
+    iid: Option<String>,
+    x: i32,
+    z: i32,
+) {
+    let mut pickup = commands.spawn((
+        ItemPickup(item),
+        Position {
+            direction: Direction::Up,
+            x,
+            z,
+        },
+        PbrBundle {
+            mesh: item_assets.mesh.clone(),
+            material: item_assets.material.clone(),
+            transform: Transform::from_xyz(x as f32, 0.075, z as f32),
+            ..default()
+        },
+    ));
+    // dropped items have no level entity and are tracked by tile instead
+    if let Some(iid) = iid {
+        pickup.insert(EntityIid(iid));
+    }
+}
+
+pub fn spawn_items(
+    mut commands: Commands,
+    item_assets: Res<ItemAssets>,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    world_state: Res<WorldState>,
+    spawn_events: EventReader<SpawnDungeonEvent>,
+) {
+    if spawn_events.is_empty() {
+        return;
+    }
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    for entity in level.entities().iter() {
+        let (EntityType::Item, Some(item)) = (&entity.entity_type, &entity.item) else {
+            continue;
+        };
+        if world_state.is_removed(dungeon_level.0, &entity.iid) {
+            continue;
+        }
+        spawn_item(
+            &mut commands,
+            &item_assets,
+            item.clone(),
+            Some(entity.iid.clone()),
+            entity.x,
+            entity.z,
+        );
+    }
+    let dropped = world_state
+        .level(dungeon_level.0)
+        .map(|state| &state.dropped);
+    for (x, z, item) in dropped.into_iter().flatten() {
+        spawn_item(&mut commands, &item_assets, item.clone(), None, *x, *z);
+    }
+}
+
+/// Picks up every item on the tile the player steps onto. Turning in place
+/// does not count, so a dropped item stays until the player comes back.
+#[allow(clippy::too_many_arguments)]
+pub fn pick_up_items(
+    mut commands: Commands,
+    dungeon_level: Res<DungeonLevel>,
+    mut inventory: ResMut<Inventory>,
+    mut world_state: ResMut<WorldState>,
+    player_query: Query<&Position, (With<Player>, Changed<Position>)>,
+    item_query: Query<(Entity, &ItemPickup, Option<&EntityIid>, &Position), Without<Player>>,
+    mut last_tile: Local<Option<(usize, i32, i32)>>,
+    mut message_events: EventWriter<MessageEvent>,
+) {
+    let Ok(position) = player_query.get_single() else {
+        return;
+    };
+    let tile = (dungeon_level.0, position.x, position.z);
+    if *last_tile == Some(tile) {
+        return;
+    }
+    *last_tile = Some(tile);
+
+    for (entity, pickup, iid, _) in item_query
+        .iter()
+        .filter(|(_, _, _, item)| item.x == position.x && item.z == position.z)
+    {
+        let state = world_state.level_mut(dungeon_level.0);
+        match iid {
+            Some(iid) => {
+                state.removed.insert(iid.0.clone());
+            }
+            None => {
+                if let Some(index) = state.dropped.iter().position(|(x, z, item)| {
+                    (*x, *z) == (position.x, position.z) && *item == pickup.0
+                }) {
+                    state.dropped.remove(index);
+                }
+            }
+        }
+        inventory.items.push(pickup.0.clone());
+        commands.entity(entity).despawn();
+        message_events.send(MessageEvent(format!("Got {}", item_name(&pickup.0))));
+    }
+}
+
+pub fn setup_inventory(mut commands: Commands, ui_font: Res<UiFont>) {
+    let text_style = TextStyle {
+        font: ui_font.0.clone(),
+        font_size: 12.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn((
+            InventoryPanel,
+            NodeBundle {
+                z_index: ZIndex::Global(125),
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(72.0),
+                    right: Val::Px(62.0),
+                    top: Val::Px(34.0),
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(4.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.85).into(),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section("Items", text_style.clone()));
+            parent.spawn((
+                InventoryList,
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    },
+                    ..default()
+                },
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        margin: UiRect::top(Val::Px(2.0)),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for command in INVENTORY_COMMANDS {
+                        parent
+                            .spawn((
+                                InventoryCommandButton(command),
+                                ButtonBundle {
+                                    style: Style {
+                                        width: Val::Px(44.0),
+                                        height: Val::Px(16.0),
+                                        justify_content: JustifyContent::Center,
+                                        align_items: AlignItems::Center,
+                                        margin: UiRect::all(Val::Px(1.0)),
+                                        ..default()
+                                    },
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn((
+                                    InventoryCommandText(command),
+                                    TextBundle::from_section(command.label(), text_style.clone()),
+                                ));
+                            });
+                    }
+                });
+        });
+}
+
+pub fn toggle_inventory(
+    mut action_events: EventReader<ActionEvent>,
+    player_lock: PlayerLock,
+    mut inventory_screen: ResMut<InventoryScreen>,
+) {
+    if !action_events
+        .iter()
+        .any(|event| event.0 == Action::Inventory)
+    {
+        return;
+    }
+    if inventory_screen.0.is_some() {
+        inventory_screen.0 = None;
+    } else if !player_lock.is_locked() {
+        inventory_screen.0 = Some((0, 0));
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_inventory(
+    mut commands: Commands,
+    item_assets: Res<ItemAssets>,
+    dungeon_level: Res<DungeonLevel>,
+    mut action_events: EventReader<ActionEvent>,
+    item_button_query: Query<(&Interaction, &InventoryItemButton), Changed<Interaction>>,
+    command_button_query: Query<(&Interaction, &InventoryCommandButton), Changed<Interaction>>,
+    player_query: Query<&Position, With<Player>>,
+    mut inventory_screen: ResMut<InventoryScreen>,
+    mut inventory: ResMut<Inventory>,
+    mut player_stats: ResMut<PlayerStats>,
+    mut world_state: ResMut<WorldState>,
+    mut message_events: EventWriter<MessageEvent>,
+) {
+    let actions: Vec<Action> = action_events.iter().map(|event| event.0).collect();
+    let Some((mut selected, mut command_index)) = inventory_screen.0 else {
+        return;
+    };
+    let count = inventory.items.len();
+    let commands_count = INVENTORY_COMMANDS.len();
+    let mut command = None;
+    for action in actions {
+        match action {
+            Action::Forward if count > 0 => selected = (selected + count - 1) % count,
+            Action::Backward if count > 0 => selected = (selected + 1) % count,
+            Action::TurnLeft | Action::StrafeLeft => {
+                command_index = (command_index + commands_count - 1) % commands_count
+            }
+            Action::TurnRight | Action::StrafeRight => {
+                command_index = (command_index + 1) % commands_count
+            }
+            Action::Interact => command = Some(INVENTORY_COMMANDS[command_index]),
+            _ => (),
+        }
+    }
+    for (interaction, item_button) in &item_button_query {
+        if *interaction == Interaction::Pressed {
+            selected = item_button.0;
+        }
+    }
+    for (interaction, command_button) in &command_button_query {
+        if *interaction == Interaction::Pressed {
+            command = Some(command_button.0);
+        }
+    }
+    if inventory_screen.0 != Some((selected, command_index)) {
+        inventory_screen.0 = Some((selected, command_index));
+    }
+
+    let (Some(command), Some(id)) = (command, inventory.items.get(selected).cloned()) else {
+        return;
+    };
+    match (command, item_kind(&id)) {
+        (InventoryCommand::Use, ItemKind::Heal(hp)) => {
+            let stats = &mut player_stats.stats;
+            stats.hp = (stats.hp + hp).min(stats.max_hp);
+            inventory.remove(selected);
+            message_events.send(MessageEvent(format!("Used {}", item_name(&id))));
+        }
+        (InventoryCommand::Equip, ItemKind::Weapon(_)) => {
+            inventory.weapon = if inventory.weapon.as_ref() == Some(&id) {
+                None
+            } else {
+                Some(id)
+            };
+        }
+        (InventoryCommand::Equip, ItemKind::Armor(_)) => {
+            inventory.armor = if inventory.armor.as_ref() == Some(&id) {
+                None
+            } else {
+                Some(id)
+            };
+        }
+        (InventoryCommand::Drop, _) => {
+            let Ok(position) = player_query.get_single() else {
+                return;
+            };
+            let id = inventory.remove(selected);
+            world_state.level_mut(dungeon_level.0).dropped.push((
+                position.x,
+                position.z,
+                id.clone(),
+            ));
+            spawn_item(
+                &mut commands,
+                &item_assets,
+                id,
+                None,
+                position.x,
+                position.z,
+            );
+        }
+        (InventoryCommand::Use, _) => {
+            message_events.send(MessageEvent("Can't use that".to_owned()));
+        }
+        (InventoryCommand::Equip, _) => {
+            message_events.send(MessageEvent("Can't equip that".to_owned()));
+        }
+    }
+    let count = inventory.items.len();
+    if selected >= count && count > 0 {
+        inventory_screen.0 = Some((count - 1, command_index));
+    }
+}
+
+/// Moving the selection only recolours the list. It is rebuilt when the
+/// items or the equipment change.
+#[allow(clippy::too_many_arguments)]
+pub fn update_inventory_ui(
+    mut commands: Commands,
+    ui_font: Res<UiFont>,
+    inventory: Res<Inventory>,
+    inventory_screen: Res<InventoryScreen>,
+    mut drawn: Local<Option<Inventory>>,
+    mut panel_query: Query<&mut Style, With<InventoryPanel>>,
+    list_query: Query<Entity, With<InventoryList>>,
+    mut command_query: Query<(&mut Text, &InventoryCommandText)>,
+    mut item_query: Query<(&mut Text, &InventoryItemText), Without<InventoryCommandText>>,
+) {
+    if !inventory.is_changed() && !inventory_screen.is_changed() {
+        return;
+    }
+    let mut style = panel_query.single_mut();
+    let Some((selected, command_index)) = inventory_screen.0 else {
+        style.display = Display::None;
+        return;
+    };
+    style.display = Display::Flex;
+
+    for (mut text, command_text) in &mut command_query {
+        text.sections[0].style.color = if INVENTORY_COMMANDS[command_index] == command_text.0 {
+            SELECTED_COLOR
+        } else {
+            Color::WHITE
+        };
+    }
+
+    let color_of = |index: usize, id: &str| {
+        if index == selected {
+            SELECTED_COLOR
+        } else if inventory.is_equipped(id) {
+            EQUIPPED_COLOR
+        } else {
+            Color::WHITE
+        }
+    };
+    let unchanged = drawn.as_ref().is_some_and(|drawn| {
+        drawn.items == inventory.items
+            && drawn.weapon == inventory.weapon
+            && drawn.armor == inventory.armor
+    });
+    if unchanged {
+        for (mut text, item_text) in &mut item_query {
+            text.sections[0].style.color = color_of(item_text.0, &inventory.items[item_text.0]);
+        }
+        return;
+    }
+    *drawn = Some(inventory.clone());
+
+    let list = list_query.single();
+    commands.entity(list).despawn_descendants();
+    commands.entity(list).with_children(|parent| {
+        if inventory.items.is_empty() {
+            parent.spawn(TextBundle::from_section(
+                "(empty)",
+                TextStyle {
+                    font: ui_font.0.clone(),
+                    font_size: 12.0,
+                    color: Color::GRAY,
+                },
+            ));
+        }
+        for (index, id) in inventory.items.iter().enumerate() {
+            let marker = if inventory.is_equipped(id) { "E " } else { "" };
+            parent
+                .spawn((
+                    InventoryItemButton(index),
+                    ButtonBundle {
+                        style: Style {
+                            height: Val::Px(14.0),
+                            align_items: AlignItems::Center,
+                            padding: UiRect::horizontal(Val::Px(2.0)),
+                            margin: UiRect::all(Val::Px(1.0)),
+                            ..default()
+                        },
+                        background_color: NORMAL_BUTTON.into(),
+                        ..default()
+                    },
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        InventoryItemText(index),
+                        TextBundle::from_section(
+                            format!("{}{}", marker, item_name(id)),
+                            TextStyle {
+                                font: ui_font.0.clone(),
+                                font_size: 12.0,
+                                color: color_of(index, id),
+                            },
+                        ),
+                    ));
+                });
+        }
+    });
+}
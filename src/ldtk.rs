@@ -439,6 +439,7 @@ pub enum LimitBehavior {
 
 /// If TRUE, the maxCount is a "per world" limit, if FALSE, it's a "per level". Possible
 /// values: `PerLayer`, `PerLevel`, `PerWorld`
+#[allow(clippy::enum_variant_names)]
 #[derive(Serialize, Deserialize)]
 pub enum LimitScope {
     #[serde(rename = "PerLayer")]
@@ -902,6 +903,7 @@ pub struct FieldInstance {
     /// [TilesetRect](#ldtk-TilesetRect) object.<br/>   - For **EntityRef**, the value is an
     /// [EntityReferenceInfos](#ldtk-EntityReferenceInfos) object.<br/><br/>  If the field is an
     /// array, then this `__value` will also be a JSON array.
+    #[allow(clippy::doc_lazy_continuation)]
     #[serde(rename = "__value")]
     pub value: Option<serde_json::Value>,
     /// Reference of the **Field definition** UID
@@ -1027,6 +1029,7 @@ pub struct TileInstance {
     /// "Flip bits", a 2-bits integer to represent the mirror transformations of the tile.<br/>
     /// - Bit 0 = X flip<br/>   - Bit 1 = Y flip<br/>   Examples: f=0 (no flip), f=1 (X flip
     /// only), f=2 (Y flip only), f=3 (both flips)
+    #[allow(clippy::doc_lazy_continuation)]
     pub f: i64,
     /// Pixel coordinates of the tile in the **layer** (`[x,y]` format). Don't forget optional
     /// layer offsets, if they exist!
@@ -0,0 +1,633 @@
+mod autolayer;
+mod automap;
+mod cat;
+mod combat;
+mod dialogue;
+mod door;
+pub mod dungeon;
+mod field;
+pub mod generator;
+mod input;
+mod inventory;
+mod ldtk;
+mod mechanism;
+mod player;
+pub mod position;
+mod registry;
+mod saving;
+mod stats;
+mod travel;
+mod validate;
+mod world;
+use automap::*;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::asset::ChangeWatcher;
+use bevy::{
+    prelude::*,
+    window::{Window, WindowMode},
+};
+use bevy_tweening::*;
+use cat::*;
+use combat::*;
+use dialogue::*;
+use door::*;
+use dungeon::{
+    reload_dungeon, spawn_dungeon, Dungeon, DungeonHandle, DungeonLevel, EventEntity, LdtkLoader,
+};
+use generator::DungeonSeed;
+use input::*;
+use inventory::*;
+use mechanism::*;
+use player::*;
+use position::Position;
+use registry::*;
+use saving::*;
+use stats::*;
+use travel::*;
+use world::WorldState;
+
+const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
+const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
+const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
+
+const WINDOW_WIDTH: f32 = 320.0;
+const WINDOW_HEIGHT: f32 = 224.0;
+
+pub fn run() {
+    // `dungeon-crawl validate <file.ldtk>` checks a project instead of starting the game
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("validate") {
+        std::process::exit(validate::run(&args[1..]));
+    }
+
+    let primary_window = Some(Window {
+        mode: WindowMode::BorderlessFullscreen, // hack for macOS 14
+        resolution: (WINDOW_WIDTH, WINDOW_HEIGHT).into(),
+        resizable: true,
+        title: "Dungeon".to_string(),
+        ..default()
+    });
+
+    // edited levels are reloaded while the game runs
+    let asset_plugin = AssetPlugin {
+        #[cfg(not(target_arch = "wasm32"))]
+        watch_for_changes: ChangeWatcher::with_delay(std::time::Duration::from_millis(200)),
+        ..default()
+    };
+
+    App::new()
+        .add_plugins(
+            DefaultPlugins
+                .set(ImagePlugin::default_nearest())
+                .set(WindowPlugin {
+                    primary_window,
+                    ..default()
+                })
+                .set(asset_plugin),
+        )
+        .add_plugins(TweeningPlugin)
+        .add_event::<MessageEvent>()
+        .add_event::<DespawnDungeonEvent>()
+        .add_event::<SpawnDungeonEvent>()
+        .add_event::<ActionEvent>()
+        .add_event::<TurnEvent>()
+        .add_event::<DialogueEvent>()
+        .add_event::<FightEvent>()
+        .add_event::<SignalEvent>()
+        .add_event::<DamageEvent>()
+        .add_asset::<Dialogue>()
+        .init_asset_loader::<DialogueLoader>()
+        .add_asset::<Dungeon>()
+        .init_asset_loader::<LdtkLoader>()
+        .insert_resource(Msaa::Off)
+        .init_resource::<Dungeon>()
+        .init_resource::<DungeonHandle>()
+        .init_resource::<UiFont>()
+        .init_resource::<CatAnimation>()
+        .init_resource::<WorldState>()
+        .init_resource::<DungeonSeed>()
+        .init_resource::<Explored>()
+        .init_resource::<SaveSlot>()
+        .init_resource::<SaveSlots>()
+        .init_resource::<KeyBindings>()
+        .init_resource::<Rebinding>()
+        .init_resource::<InputBuffer>()
+        .init_resource::<HeldActions>()
+        .init_resource::<DialogueFlags>()
+        .init_resource::<ActiveDialogue>()
+        .init_resource::<DialogueHandles>()
+        .init_resource::<PlayerStats>()
+        .init_resource::<ActiveFight>()
+        .init_resource::<Inventory>()
+        .init_resource::<InventoryScreen>()
+        .init_resource::<ItemAssets>()
+        .init_resource::<MechanismAssets>()
+        .add_plugins(CustomEntities)
+        .add_systems(
+            Startup,
+            (
+                setup,
+                setup_automap,
+                setup_slot_picker,
+                setup_key_config,
+                setup_touch_pad,
+                setup_dialogue,
+                setup_fight,
+                setup_hud,
+                setup_inventory,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                despawn_dungeon,
+                spawn_dungeon,
+                spawn_cats,
+                spawn_enemies,
+                spawn_items,
+                spawn_mechanisms,
+                spawn_registered_entities,
+                spawn_player,
+                load_dialogues,
+                setup_cats_animation,
+                capture_binding,
+                (
+                    read_keyboard,
+                    read_gamepad,
+                    show_touch_pad,
+                    read_touch_buttons,
+                    update_held_actions,
+                ),
+                (
+                    finish_player_motion,
+                    buffer_actions,
+                    update_travel,
+                    update_player,
+                    update_cats,
+                    pick_up_items,
+                    apply_damage,
+                )
+                    .chain(),
+                (interact_switch, update_pressure_plates, apply_signals).chain(),
+                (
+                    interact_door,
+                    reload_dungeon,
+                    update_explored,
+                    toggle_full_map,
+                    update_automap,
+                    click_automap,
+                    update_message,
+                    update_button_style,
+                    interact_window_resize_button,
+                    interact_checker_button,
+                    interact_reset_button,
+                    interact_random_button,
+                    interact_save_button,
+                    interact_load_button,
+                    interact_slot_button,
+                    update_slot_text,
+                    interact_key_config_button,
+                    interact_binding_button,
+                    update_binding_text,
+                ),
+                (start_dialogue, advance_dialogue, update_dialogue_ui).chain(),
+                (toggle_inventory, update_inventory, update_inventory_ui).chain(),
+                (
+                    start_fight,
+                    update_fight,
+                    update_fight_ui,
+                    update_damage_numbers,
+                    update_hud,
+                )
+                    .chain(),
+            )
+                .chain(),
+        )
+        .run();
+}
+
+#[derive(Event)]
+pub struct DespawnDungeonEvent;
+
+#[derive(Event)]
+pub struct SpawnDungeonEvent(Option<Position>);
+
+#[derive(Event)]
+pub struct MessageEvent(String);
+
+/// Sent after every player move or turn, so that NPCs can take theirs.
+#[derive(Event)]
+pub struct TurnEvent;
+
+/// Builders of the LDtk entities specific to this game.
+pub struct CustomEntities;
+impl Plugin for CustomEntities {
+    fn build(&self, app: &mut App) {
+        app.register_ldtk_entity("Torch", build_torch);
+    }
+}
+
+/// A light that is always on, with optional `Color` and `Range` fields.
+fn build_torch(commands: &mut bevy::ecs::system::EntityCommands, entity: &EventEntity) {
+    commands.insert(PointLightBundle {
+        point_light: PointLight {
+            color: entity
+                .field("Color")
+                .and_then(|value| value.as_color())
+                .unwrap_or(Color::rgb(1.0, 0.7, 0.4)),
+            intensity: 150.0,
+            range: entity
+                .field("Range")
+                .and_then(|value| value.as_f64())
+                .unwrap_or(3.0) as f32,
+            ..default()
+        },
+        transform: Transform::from_xyz(entity.x as f32, 0.8, entity.z as f32),
+        ..default()
+    });
+}
+
+#[derive(Component)]
+struct MessageText;
+
+#[derive(Component)]
+struct CheckerButton;
+
+#[derive(Component)]
+struct WindowResizeButton;
+
+#[derive(Component)]
+struct SaveButton;
+
+#[derive(Component)]
+struct LoadButton;
+
+#[derive(Component)]
+struct ResetButton;
+
+#[derive(Component)]
+struct RandomButton;
+
+#[derive(Component)]
+struct CheckerImage;
+
+#[derive(Resource)]
+struct UiFont(Handle<Font>);
+impl FromWorld for UiFont {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.get_resource::<AssetServer>().unwrap();
+        UiFont(asset_server.load("k8x12.ttf"))
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    ui_font: Res<UiFont>,
+    mut spawn_event: EventWriter<SpawnDungeonEvent>,
+) {
+    commands.spawn((
+        CheckerImage,
+        ImageBundle {
+            image: UiImage {
+                texture: asset_server.load("checker.png"),
+                ..default()
+            },
+            style: Style {
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            ..default()
+        },
+    ));
+    commands
+        .spawn(NodeBundle {
+            z_index: ZIndex::Local(100),
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Stretch,
+                justify_content: JustifyContent::SpaceBetween,
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            let button_bundle = ButtonBundle {
+                style: Style {
+                    width: Val::Px(40.0),
+                    height: Val::Px(20.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    margin: UiRect::all(Val::Px(2.0)),
+                    ..default()
+                },
+                background_color: NORMAL_BUTTON.into(),
+                ..default()
+            };
+            let text_style = TextStyle {
+                font: ui_font.0.clone(),
+                font_size: 12.0,
+                color: Color::WHITE,
+            };
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Px(34.0),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::FlexEnd,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn((CheckerButton, button_bundle.clone()))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section("Checker", text_style.clone()));
+                        });
+                    parent
+                        .spawn((WindowResizeButton, button_bundle.clone()))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section("Resize", text_style.clone()));
+                        });
+                    parent
+                        .spawn((ResetButton, button_bundle.clone()))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section("Reset", text_style.clone()));
+                        });
+                    parent
+                        .spawn((RandomButton, button_bundle.clone()))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section("Random", text_style.clone()));
+                        });
+                    parent
+                        .spawn((LoadButton, button_bundle.clone()))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section("Load", text_style.clone()));
+                        });
+                    parent
+                        .spawn((SaveButton, button_bundle.clone()))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section("Save", text_style.clone()));
+                        });
+                    parent
+                        .spawn((KeyConfigButton, button_bundle))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section("Keys", text_style));
+                        });
+                });
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(100.0),
+                        height: Val::Px(100.0),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        MessageText,
+                        TextBundle::from_section(
+                            "",
+                            TextStyle {
+                                font: ui_font.0.clone(),
+                                font_size: 24.0,
+                                color: Color::WHITE,
+                            },
+                        ),
+                    ));
+                });
+        });
+    commands.insert_resource(DungeonLevel(0));
+    spawn_event.send(SpawnDungeonEvent(None));
+}
+
+fn update_message(
+    mut message_events: EventReader<MessageEvent>,
+    mut query: Query<&mut Text, With<MessageText>>,
+) {
+    if message_events.is_empty() {
+        return;
+    }
+    let mut text = query.single_mut();
+    for ev in message_events.iter() {
+        text.sections[0].value = ev.0.clone()
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn update_button_style(
+    mut interaction_query: Query<
+        (&Interaction, &mut BackgroundColor),
+        (Changed<Interaction>, With<Button>),
+    >,
+) {
+    for (interaction, mut color) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => *color = PRESSED_BUTTON.into(),
+            Interaction::Hovered => *color = HOVERED_BUTTON.into(),
+            Interaction::None => *color = NORMAL_BUTTON.into(),
+        }
+    }
+}
+fn interact_checker_button(
+    mut image_style: Query<&mut Style, With<CheckerImage>>,
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<CheckerButton>)>,
+) {
+    for interaction in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let mut style = image_style.single_mut();
+
+        if style.display == Display::None {
+            style.display = Display::Flex;
+        } else {
+            style.display = Display::None;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn interact_save_button(
+    position_query: Query<&Position, With<Player>>,
+    level: Res<DungeonLevel>,
+    world_state: Res<WorldState>,
+    inventory: Res<Inventory>,
+    dungeon_seed: Res<DungeonSeed>,
+    explored: Res<Explored>,
+    key_bindings: Res<KeyBindings>,
+    dialogue_flags: Res<DialogueFlags>,
+    player_stats: Res<PlayerStats>,
+    save_slot: Res<SaveSlot>,
+    mut save_slots: ResMut<SaveSlots>,
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<SaveButton>)>,
+    mut message_events: EventWriter<MessageEvent>,
+) {
+    if position_query.is_empty() {
+        return;
+    }
+    for interaction in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let player_position = position_query.single();
+        let save_data = SaveData {
+            version: SAVE_VERSION,
+            timestamp: now(),
+            dungeon_level: DungeonLevel(level.0),
+            player_position: player_position.clone(),
+            world_state: world_state.clone(),
+            inventory: inventory.clone(),
+            dungeon_seed: dungeon_seed.clone(),
+            explored: explored.clone(),
+            key_bindings: key_bindings.clone(),
+            dialogue_flags: dialogue_flags.clone(),
+            player_stats: player_stats.clone(),
+        };
+        match save_game(save_slot.0, &save_data) {
+            Ok(()) => save_slots.0[save_slot.0] = Some(save_data.timestamp),
+            Err(error) => {
+                warn!("Failed to save: {}", error);
+                message_events.send(MessageEvent("Save failed".to_owned()));
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn interact_load_button(
+    mut commands: Commands,
+    dungeon_handle: Res<DungeonHandle>,
+    dungeons: Res<Assets<Dungeon>>,
+    save_slot: Res<SaveSlot>,
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<LoadButton>)>,
+    mut despawn_events: EventWriter<DespawnDungeonEvent>,
+    mut spawn_events: EventWriter<SpawnDungeonEvent>,
+    mut message_events: EventWriter<MessageEvent>,
+) {
+    for interaction in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let position = match load_game(save_slot.0) {
+            Ok(mut save_data) => {
+                save_data.key_bindings.fill_missing();
+                commands.insert_resource(match save_data.dungeon_seed.0 {
+                    Some(seed) => Dungeon::generate(seed),
+                    None => dungeon_handle.get(&dungeons),
+                });
+                commands.insert_resource(save_data.dungeon_seed);
+                commands.insert_resource(save_data.dungeon_level);
+                commands.insert_resource(save_data.world_state);
+                commands.insert_resource(save_data.explored);
+                commands.insert_resource(save_data.key_bindings);
+                commands.insert_resource(save_data.dialogue_flags);
+                commands.insert_resource(save_data.player_stats);
+                commands.insert_resource(save_data.inventory);
+                commands.insert_resource(ActiveDialogue::default());
+                commands.insert_resource(ActiveFight::default());
+                commands.insert_resource(InventoryScreen::default());
+                Some(save_data.player_position)
+            }
+            Err(SaveError::NotFound) => {
+                insert_new_game(&mut commands, dungeon_handle.get(&dungeons), None);
+                None
+            }
+            Err(error) => {
+                warn!("Failed to load: {}", error);
+                message_events.send(MessageEvent("Load failed".to_owned()));
+                continue;
+            }
+        };
+
+        despawn_events.send(DespawnDungeonEvent);
+        spawn_events.send(SpawnDungeonEvent(position));
+    }
+}
+
+fn interact_window_resize_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<WindowResizeButton>)>,
+    mut windows: Query<&mut Window>,
+) {
+    for interaction in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let mut window = windows.single_mut();
+        window.mode = WindowMode::Windowed;
+        window.resolution.set(WINDOW_WIDTH, WINDOW_HEIGHT);
+    }
+}
+
+/// Resets every piece of game progress for a new game in `dungeon`.
+fn insert_new_game(commands: &mut Commands, dungeon: Dungeon, seed: Option<u64>) {
+    commands.insert_resource(dungeon);
+    commands.insert_resource(DungeonSeed(seed));
+    commands.insert_resource(DungeonLevel(0));
+    commands.insert_resource(WorldState::default());
+    commands.insert_resource(Explored::default());
+    commands.insert_resource(DialogueFlags::default());
+    commands.insert_resource(ActiveDialogue::default());
+    commands.insert_resource(ActiveFight::default());
+    commands.insert_resource(PlayerStats::default());
+    commands.insert_resource(Inventory::default());
+    commands.insert_resource(InventoryScreen::default());
+}
+
+fn interact_reset_button(
+    mut commands: Commands,
+    dungeon_handle: Res<DungeonHandle>,
+    dungeons: Res<Assets<Dungeon>>,
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<ResetButton>)>,
+    mut despawn_events: EventWriter<DespawnDungeonEvent>,
+    mut spawn_events: EventWriter<SpawnDungeonEvent>,
+) {
+    for interaction in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        insert_new_game(&mut commands, dungeon_handle.get(&dungeons), None);
+        despawn_events.send(DespawnDungeonEvent);
+        spawn_events.send(SpawnDungeonEvent(None));
+    }
+}
+
+fn interact_random_button(
+    mut commands: Commands,
+    time: Res<Time>,
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<RandomButton>)>,
+    mut despawn_events: EventWriter<DespawnDungeonEvent>,
+    mut spawn_events: EventWriter<SpawnDungeonEvent>,
+) {
+    for interaction in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let seed = time.raw_elapsed().as_nanos() as u64;
+        insert_new_game(&mut commands, Dungeon::generate(seed), Some(seed));
+        despawn_events.send(DespawnDungeonEvent);
+        spawn_events.send(SpawnDungeonEvent(None));
+    }
+}
+
+fn despawn_dungeon(
+    mut commands: Commands,
+    query: Query<(Entity, &Transform, Without<Node>)>,
+    mut reset_events: EventReader<DespawnDungeonEvent>,
+) {
+    if reset_events.is_empty() {
+        return;
+    }
+    for _ in reset_events.iter() {}
+
+    for (entity, _, _) in query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
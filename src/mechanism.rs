@@ -0,0 +1,320 @@
+use crate::door::{get_door_animator, get_door_transform, DoorPanel, DoorState};
+use crate::dungeon::{Dungeon, DungeonLevel, EntityLink, EntityType, EventEntity};
+use crate::input::{Action, ActionEvent};
+use crate::player::{Player, PlayerLock};
+use crate::position::{Direction, Position};
+use crate::world::{EntityIid, WorldState};
+use crate::SpawnDungeonEvent;
+use bevy::prelude::*;
+use bevy_tweening::{lens::*, *};
+use std::time::Duration;
+
+const SWITCH_ON_COLOR: Color = Color::rgb(0.35, 0.75, 0.35);
+const SWITCH_OFF_COLOR: Color = Color::rgb(0.75, 0.25, 0.2);
+
+/// Toggles the linked entity: opens or closes an unlocked door, raises or lowers a
+/// wall, extends or retracts a bridge, turns a light on or off. Switches and
+/// plates pass the signal on to their `Targets`.
+#[derive(Event)]
+pub struct SignalEvent(pub EntityLink);
+
+/// A spawned switch, plate, wall, bridge or light.
+#[derive(Component)]
+pub struct Mechanism(pub EntityType);
+
+#[derive(Resource)]
+pub struct MechanismAssets {
+    switch_mesh: Handle<Mesh>,
+    plate_mesh: Handle<Mesh>,
+    wall_mesh: Handle<Mesh>,
+    bridge_mesh: Handle<Mesh>,
+    switch_on: Handle<StandardMaterial>,
+    switch_off: Handle<StandardMaterial>,
+    stone: Handle<StandardMaterial>,
+    wood: Handle<StandardMaterial>,
+}
+impl FromWorld for MechanismAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        let switch_mesh = meshes.add(shape::Box::new(0.1, 0.2, 0.05).into());
+        let plate_mesh = meshes.add(shape::Box::new(0.6, 0.04, 0.6).into());
+        let wall_mesh = meshes.add(shape::Cube { size: 1.0 }.into());
+        let bridge_mesh = meshes.add(shape::Box::new(0.9, 0.05, 1.0).into());
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let mut material = |color: Color| {
+            materials.add(StandardMaterial {
+                base_color: color,
+                unlit: false,
+                ..default()
+            })
+        };
+        MechanismAssets {
+            switch_mesh,
+            plate_mesh,
+            wall_mesh,
+            bridge_mesh,
+            switch_on: material(SWITCH_ON_COLOR),
+            switch_off: material(SWITCH_OFF_COLOR),
+            stone: material(Color::rgb(0.45, 0.45, 0.5)),
+            wood: material(Color::rgb(0.5, 0.35, 0.2)),
+        }
+    }
+}
+
+fn get_mechanism_transform(
+    entity_type: &EntityType,
+    position: &Position,
+    active: bool,
+) -> Transform {
+    let (x, z) = (position.x as f32, position.z as f32);
+    match entity_type {
+        // on the wall the switch faces, at eye height
+        EntityType::Switch => {
+            let mut transform = Transform::from_translation(
+                Vec3::new(x, 0.5, z)
+                    + match position.direction {
+                        Direction::Up => Vec3::NEG_Z,
+                        Direction::Right => Vec3::X,
+                        Direction::Down => Vec3::Z,
+                        Direction::Left => Vec3::NEG_X,
+                    } * 0.48,
+            );
+            if matches!(position.direction, Direction::Right | Direction::Left) {
+                transform.rotate_y(std::f32::consts::PI * 0.5);
+            }
+            transform
+        }
+        EntityType::PressurePlate => Transform::from_xyz(x, if active { 0.0 } else { 0.02 }, z),
+        EntityType::Wall => Transform::from_xyz(x, if active { 0.5 } else { -0.49 }, z),
+        EntityType::Light => Transform::from_xyz(x, 0.8, z),
+        _ => Transform::from_xyz(x, 0.0, z),
+    }
+}
+
+fn get_visibility(entity_type: &EntityType, active: bool) -> Visibility {
+    match entity_type {
+        EntityType::Bridge | EntityType::Light if !active => Visibility::Hidden,
+        _ => Visibility::Inherited,
+    }
+}
+
+fn get_position(entity: &EventEntity) -> Position {
+    Position {
+        direction: entity.direction.clone(),
+        x: entity.x,
+        z: entity.z,
+    }
+}
+
+pub fn spawn_mechanisms(
+    mut commands: Commands,
+    assets: Res<MechanismAssets>,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    world_state: Res<WorldState>,
+    spawn_events: EventReader<SpawnDungeonEvent>,
+) {
+    if spawn_events.is_empty() {
+        return;
+    }
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    for entity in level.entities().iter() {
+        let (mesh, material) = match entity.entity_type {
+            EntityType::Switch => (assets.switch_mesh.clone(), assets.switch_off.clone()),
+            EntityType::PressurePlate => (assets.plate_mesh.clone(), assets.stone.clone()),
+            EntityType::Wall => (assets.wall_mesh.clone(), assets.stone.clone()),
+            EntityType::Bridge => (assets.bridge_mesh.clone(), assets.wood.clone()),
+            EntityType::Light => (Handle::default(), Handle::default()),
+            _ => continue,
+        };
+        let active = world_state.is_active(dungeon_level.0, entity);
+        let transform = get_mechanism_transform(&entity.entity_type, &get_position(entity), active);
+        let visibility = get_visibility(&entity.entity_type, active);
+        let mut spawned = match entity.entity_type {
+            EntityType::Light => commands.spawn(PointLightBundle {
+                point_light: PointLight {
+                    color: Color::rgb(1.0, 0.85, 0.6),
+                    intensity: 200.0,
+                    range: 4.0,
+                    ..default()
+                },
+                transform,
+                visibility,
+                ..default()
+            }),
+            EntityType::Switch => commands.spawn(PbrBundle {
+                mesh,
+                material: if active {
+                    assets.switch_on.clone()
+                } else {
+                    material
+                },
+                transform,
+                visibility,
+                ..default()
+            }),
+            _ => commands.spawn(PbrBundle {
+                mesh,
+                material,
+                transform,
+                visibility,
+                ..default()
+            }),
+        };
+        spawned.insert((
+            Mechanism(entity.entity_type.clone()),
+            EntityIid(entity.iid.clone()),
+        ));
+    }
+}
+
+/// Flips a switch on the wall the player faces.
+pub fn interact_switch(
+    mut action_events: EventReader<ActionEvent>,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    player_lock: PlayerLock,
+    player_query: Query<&Position, With<Player>>,
+    mut signal_events: EventWriter<SignalEvent>,
+) {
+    let interacted = action_events
+        .iter()
+        .any(|event| event.0 == Action::Interact);
+    let Ok(position) = player_query.get_single() else {
+        return;
+    };
+    if !interacted || player_lock.is_locked() {
+        return;
+    }
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    let Some(switch) = level.entities_at(position.x, position.z).find(|entity| {
+        matches!(entity.entity_type, EntityType::Switch) && entity.direction == position.direction
+    }) else {
+        return;
+    };
+    signal_events.send(SignalEvent(EntityLink {
+        level: dungeon_level.0,
+        iid: switch.iid.clone(),
+    }));
+}
+
+/// Presses a plate when the player steps onto it and releases it when they step off.
+pub fn update_pressure_plates(
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    world_state: Res<WorldState>,
+    player_query: Query<&Position, (With<Player>, Changed<Position>)>,
+    mut signal_events: EventWriter<SignalEvent>,
+) {
+    let Ok(position) = player_query.get_single() else {
+        return;
+    };
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    for plate in level
+        .entities()
+        .iter()
+        .filter(|entity| matches!(entity.entity_type, EntityType::PressurePlate))
+    {
+        let pressed = plate.x == position.x && plate.z == position.z;
+        if pressed != world_state.is_active(dungeon_level.0, plate) {
+            signal_events.send(SignalEvent(EntityLink {
+                level: dungeon_level.0,
+                iid: plate.iid.clone(),
+            }));
+        }
+    }
+}
+
+/// Decides how each signalled entity reacts, and moves the spawned ones on
+/// the current level to match. Targets on other levels only change state.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn apply_signals(
+    mut commands: Commands,
+    mut signal_events: EventReader<SignalEvent>,
+    assets: Res<MechanismAssets>,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    mut world_state: ResMut<WorldState>,
+    door_query: Query<(Entity, &Transform, &DoorPanel)>,
+    mut mechanism_query: Query<(
+        Entity,
+        &Mechanism,
+        &EntityIid,
+        &Transform,
+        &mut Visibility,
+        Option<&mut Handle<StandardMaterial>>,
+    )>,
+) {
+    let mut queue: Vec<EntityLink> = signal_events.iter().map(|event| event.0.clone()).collect();
+    // every entity reacts once per frame, so linked switches can't loop forever
+    let mut signalled: Vec<EntityLink> = vec![];
+    while let Some(link) = queue.pop() {
+        if signalled.contains(&link) {
+            continue;
+        }
+        signalled.push(link.clone());
+        let Some(level) = dungeon.levels.get(link.level) else {
+            continue;
+        };
+        let current = link.level == dungeon_level.0;
+
+        if let Some((door_index, door)) = level.get_door_by_iid(&link.iid) {
+            let state = match world_state.door_state(link.level, door) {
+                DoorState::Open => DoorState::Closed,
+                DoorState::Closed => DoorState::Open,
+                // only the door's key opens a locked door
+                DoorState::Locked => continue,
+            };
+            world_state.set_door_state(link.level, door, state);
+            if !current {
+                continue;
+            }
+            let end = get_door_transform(&door.direction, door.x as f32, door.z as f32, state)
+                .translation;
+            for (entity, transform, _) in door_query.iter().filter(|(_, _, p)| p.0 == door_index) {
+                commands
+                    .entity(entity)
+                    .insert(get_door_animator(transform, end));
+            }
+            continue;
+        }
+
+        let Some(target) = level.get_entity_by_iid(&link.iid) else {
+            warn!("Signal to unknown entity {}", link.iid);
+            continue;
+        };
+        world_state.toggle(link.level, &target.iid);
+        if matches!(
+            target.entity_type,
+            EntityType::Switch | EntityType::PressurePlate
+        ) {
+            queue.extend(target.links("Targets"));
+        }
+        if !current {
+            continue;
+        }
+        let active = world_state.is_active(link.level, target);
+        for (entity, mechanism, _, transform, mut visibility, material) in mechanism_query
+            .iter_mut()
+            .filter(|(_, _, iid, _, _, _)| iid.0 == target.iid)
+        {
+            *visibility = get_visibility(&mechanism.0, active);
+            if let (EntityType::Switch, Some(mut material)) = (&mechanism.0, material) {
+                *material = if active {
+                    assets.switch_on.clone()
+                } else {
+                    assets.switch_off.clone()
+                };
+            }
+            let end = get_mechanism_transform(&mechanism.0, &get_position(target), active);
+            commands.entity(entity).insert(Animator::new(Tween::new(
+                EaseFunction::QuadraticInOut,
+                Duration::from_millis(400),
+                TransformPositionLens {
+                    start: transform.translation,
+                    end: end.translation,
+                },
+            )));
+        }
+    }
+}
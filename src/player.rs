@@ -1,14 +1,70 @@
-use crate::dungeon::{Dungeon, DungeonLevel, EntityType};
+use crate::cat::Cat;
+use crate::combat::{ActiveFight, Enemy, FightEvent};
+use crate::dialogue::{ActiveDialogue, DialogueEvent};
+use crate::dungeon::{Dungeon, DungeonLevel, EntityType, Terrain};
+use crate::input::{Action, ActionEvent, HeldActions, InputBuffer};
+use crate::inventory::InventoryScreen;
 use crate::position::{get_transform, Direction, Position};
-use crate::{MessageEvent, SpawnDungeonEvent};
+use crate::stats::{DamageEvent, PlayerStats};
+use crate::travel::Travel;
+use crate::world::WorldState;
+use crate::{DespawnDungeonEvent, MessageEvent, SpawnDungeonEvent, TurnEvent};
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use bevy_tweening::{lens::*, *};
+use std::collections::VecDeque;
 use std::f32::consts::PI;
 use std::time::Duration;
 
+/// `user_data` of the completion event sent when a player tween finishes.
+const PLAYER_TWEEN_COMPLETED: u64 = 1;
+const FLOOR_DAMAGE: i32 = 1;
+const FALL_DAMAGE: i32 = 3;
+
 #[derive(Component)]
 pub struct Player;
 
+/// Whatever keeps the player from acting: a dialogue, a fight, death or the
+/// inventory screen.
+#[derive(SystemParam)]
+pub struct PlayerLock<'w> {
+    active_dialogue: Res<'w, ActiveDialogue>,
+    active_fight: Res<'w, ActiveFight>,
+    player_stats: Res<'w, PlayerStats>,
+    inventory_screen: Res<'w, InventoryScreen>,
+}
+impl PlayerLock<'_> {
+    pub fn is_locked(&self) -> bool {
+        self.active_dialogue.0.is_some()
+            || self.active_fight.0.is_some()
+            || self.player_stats.is_dead()
+            || self.inventory_screen.0.is_some()
+    }
+}
+
+/// Takes the player to another level, onto `arrival` or the PlayerStart.
+#[derive(SystemParam)]
+pub struct LevelTransition<'w> {
+    despawn_events: EventWriter<'w, DespawnDungeonEvent>,
+    spawn_events: EventWriter<'w, SpawnDungeonEvent>,
+}
+impl LevelTransition<'_> {
+    pub fn go(&mut self, commands: &mut Commands, level: usize, arrival: Option<Position>) {
+        commands.insert_resource(DungeonLevel(level));
+        self.despawn_events.send(DespawnDungeonEvent);
+        self.spawn_events.send(SpawnDungeonEvent(arrival));
+    }
+}
+
+/// Moves wait in `queue` while the player is `moving`, that is until the
+/// current tween reports completion.
+#[derive(Component, Default)]
+pub struct PlayerMotion {
+    pub queue: VecDeque<Action>,
+    pub moving: bool,
+    pub last: Option<Action>,
+}
+
 pub fn spawn_player(
     mut commands: Commands,
     dungeon: Res<Dungeon>,
@@ -37,20 +93,14 @@ pub fn spawn_player(
             player_position.direction = position.direction.clone();
         }
         None => {
-            for entity in level.entities.iter() {
-                match entity.entity_type {
-                    EntityType::PlayerStart => {
-                        camera_transform = get_player_transform(
-                            &entity.direction,
-                            entity.x as f32,
-                            entity.z as f32,
-                        );
-                        player_position.x = entity.x;
-                        player_position.z = entity.z;
-                        player_position.direction = entity.direction.clone();
-                    }
-                    _ => (),
-                };
+            for entity in level.entities().iter() {
+                if let EntityType::PlayerStart = entity.entity_type {
+                    camera_transform =
+                        get_player_transform(&entity.direction, entity.x as f32, entity.z as f32);
+                    player_position.x = entity.x;
+                    player_position.z = entity.z;
+                    player_position.direction = entity.direction.clone();
+                }
             }
         }
     }
@@ -58,6 +108,8 @@ pub fn spawn_player(
     commands
         .spawn((
             Player,
+            PlayerMotion::default(),
+            Travel::default(),
             player_position,
             SpatialBundle {
                 transform: camera_transform,
@@ -84,7 +136,7 @@ pub fn spawn_player(
         });
 }
 
-fn get_cannot_move_animator(
+pub fn get_cannot_move_animator(
     transform: &Transform,
     now_position: &Position,
     new_position: &Position,
@@ -133,7 +185,8 @@ fn get_cannot_move_animator(
                 start: between_transform.translation,
                 end: now_transform.translation,
             },
-        ),
+        )
+        .with_completed_event(PLAYER_TWEEN_COMPLETED),
     ]);
     Animator::<Transform>::new(Sequence::new(vec![tracks1, tracks2]))
 }
@@ -160,57 +213,147 @@ fn get_move_animator(transform: &Transform, new_position: &Position) -> Animator
                 start: transform.translation,
                 end: new_transform.translation,
             },
-        ),
+        )
+        .with_completed_event(PLAYER_TWEEN_COMPLETED),
     ]))
 }
 
+/// Queues the player's moves, dropping the ones past the buffer depth.
+pub fn buffer_actions(
+    mut action_events: EventReader<ActionEvent>,
+    input_buffer: Res<InputBuffer>,
+    player_lock: PlayerLock,
+    mut query: Query<&mut PlayerMotion>,
+) {
+    // moves made while the player is locked are dropped
+    let Ok(mut motion) = query.get_single_mut() else {
+        action_events.clear();
+        return;
+    };
+    if player_lock.is_locked() {
+        action_events.clear();
+        return;
+    }
+    for action in action_events.iter().map(|event| event.0) {
+        if !action.is_move() {
+            continue;
+        }
+        // the first move of an idle player does not count against the buffer
+        let idle = !motion.moving && motion.queue.is_empty();
+        if idle || motion.queue.len() < input_buffer.depth {
+            motion.queue.push_back(action);
+        }
+    }
+}
+
+pub fn finish_player_motion(
+    mut completed_events: EventReader<TweenCompleted>,
+    mut query: Query<&mut PlayerMotion>,
+) {
+    for event in completed_events.iter() {
+        if event.user_data != PLAYER_TWEEN_COMPLETED {
+            continue;
+        }
+        if let Ok(mut motion) = query.get_mut(event.entity) {
+            motion.moving = false;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn update_player(
-    keys: Res<Input<KeyCode>>,
+    input_buffer: Res<InputBuffer>,
+    held_actions: Res<HeldActions>,
     dungeon: Res<Dungeon>,
     mut commands: Commands,
-    mut query: Query<(Entity, &Transform, &mut Position), With<Player>>,
+    mut query: Query<(Entity, &Transform, &mut Position, &mut PlayerMotion), With<Player>>,
+    cat_query: Query<(&Position, &Cat), Without<Player>>,
+    enemy_query: Query<(Entity, &Position), (With<Enemy>, Without<Player>)>,
     mut message_events: EventWriter<MessageEvent>,
+    mut turn_events: EventWriter<TurnEvent>,
+    mut dialogue_events: EventWriter<DialogueEvent>,
+    mut fight_events: EventWriter<FightEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    player_lock: PlayerLock,
+    mut level_transition: LevelTransition,
     dungeon_level: Res<DungeonLevel>,
+    world_state: Res<WorldState>,
 ) {
     if query.is_empty() {
         return;
     }
     let level = dungeon.levels.get(dungeon_level.0).unwrap();
 
-    let (entity, transform, mut position) = query.single_mut();
-    let tile = level.get_tile(position.x, position.z);
+    let (entity, transform, mut position, mut motion) = query.single_mut();
+    if motion.moving || player_lock.is_locked() {
+        return;
+    }
+    // a held step repeats once the queue runs dry
+    let repeat = motion.last.filter(|action| {
+        input_buffer.auto_repeat && held_actions.0.contains(action) && action.is_step()
+    });
+    let Some(action) = motion.queue.pop_front().or(repeat) else {
+        motion.last = None;
+        return;
+    };
 
-    let (tweened, wall_position): (bool, Option<Position>) = if keys.just_pressed(KeyCode::W) {
-        if tile.is_some() && !tile.unwrap().has_wall(&position.direction) {
-            position.go_forward();
-            (true, None)
-        } else {
-            let mut wall = position.clone();
-            wall.go_forward();
-            (true, Some(wall))
-        }
-    } else if keys.just_pressed(KeyCode::S) {
-        if tile.is_some() && !tile.unwrap().has_wall(&position.direction.reverse()) {
-            position.go_backward();
-            (true, None)
+    let from = position.clone();
+    let is_water =
+        |x: i32, z: i32| world_state.terrain(level, dungeon_level.0, x, z) == Some(Terrain::Water);
+    let can_move = |direction: &Direction| {
+        level.can_move(&from, direction)
+            && world_state.is_passable(level, dungeon_level.0, from.x, from.z, direction)
+    };
+    let cat_at = |x: i32, z: i32| {
+        cat_query
+            .iter()
+            .find(|(position, _)| position.x == x && position.z == z)
+            .map(|(_, cat)| cat)
+    };
+    let enemy_at = |x: i32, z: i32| {
+        enemy_query
+            .iter()
+            .find(|(_, position)| position.x == x && position.z == z)
+            .map(|(enemy, _)| enemy)
+    };
+    let step = |position: &mut Position, direction: Direction| {
+        let mut next = position.clone();
+        next.go(&direction);
+        if can_move(&direction)
+            && !is_water(next.x, next.z)
+            && cat_at(next.x, next.z).is_none()
+            && enemy_at(next.x, next.z).is_none()
+        {
+            *position = next;
+            None
         } else {
-            let mut wall = position.clone();
-            wall.go_backward();
-            (true, Some(wall))
-        }
-    } else if keys.just_pressed(KeyCode::A) {
-        position.rotate_left();
-        (true, None)
-    } else if keys.just_pressed(KeyCode::D) {
-        position.rotate_right();
-        (true, None)
-    } else {
-        (false, None)
+            Some(next)
+        }
     };
 
-    if !tweened {
-        return;
+    let facing = position.direction.clone();
+    let wall_position = match action {
+        Action::Forward => step(&mut position, facing),
+        Action::Backward => step(&mut position, facing.reverse()),
+        Action::StrafeLeft => step(&mut position, facing.left()),
+        Action::StrafeRight => step(&mut position, facing.right()),
+        Action::TurnLeft => {
+            position.rotate_left();
+            None
+        }
+        Action::TurnRight => {
+            position.rotate_right();
+            None
+        }
+        Action::TurnAround => {
+            position.direction = facing.reverse();
+            None
+        }
+        Action::Interact | Action::Map | Action::Inventory | Action::Explore => return,
     };
+    motion.last = Some(action);
+    motion.moving = true;
+    turn_events.send(TurnEvent);
 
     if let Some(wall_position) = wall_position {
         commands.entity(entity).insert(get_cannot_move_animator(
@@ -218,6 +361,24 @@ pub fn update_player(
             &position,
             &wall_position,
         ));
+        if let Some(enemy) = enemy_at(wall_position.x, wall_position.z) {
+            fight_events.send(FightEvent(enemy));
+        }
+        // bumping into a cat talks to it
+        match cat_at(wall_position.x, wall_position.z) {
+            Some(Cat {
+                dialogue: Some(dialogue),
+                ..
+            }) => dialogue_events.send(DialogueEvent(dialogue.clone())),
+            Some(Cat {
+                message: Some(message),
+                ..
+            }) => message_events.send(MessageEvent(message.clone())),
+            _ => (),
+        }
+        if is_water(wall_position.x, wall_position.z) {
+            message_events.send(MessageEvent("The water is too deep".to_owned()));
+        }
         return;
     }
 
@@ -225,7 +386,22 @@ pub fn update_player(
         .entity(entity)
         .insert(get_move_animator(transform, &position));
 
+    // walking off the edge of the level leads into the neighbouring one
+    if let Some((target, arrival)) = dungeon.get_neighbour(dungeon_level.0, &position) {
+        level_transition.go(&mut commands, target, Some(arrival));
+        return;
+    }
+
     match level.get_entity(position.x, position.z) {
+        Some(event_entity) if event_entity.destination.is_some() => {
+            if let Some((target, arrival)) = dungeon.get_destination(dungeon_level.0, event_entity)
+            {
+                level_transition.go(&mut commands, target, arrival);
+            }
+        }
+        Some(event_entity) if event_entity.dialogue.is_some() => {
+            dialogue_events.send(DialogueEvent(event_entity.dialogue.clone().unwrap()))
+        }
         Some(event_entity) => {
             if event_entity.message.is_some() {
                 message_events.send(MessageEvent(event_entity.message.clone().unwrap()))
@@ -233,6 +409,31 @@ pub fn update_player(
         }
         _ => message_events.send(MessageEvent("".to_owned())),
     };
+
+    match world_state.terrain(level, dungeon_level.0, position.x, position.z) {
+        Some(Terrain::Pit) => {
+            damage_events.send(DamageEvent(FALL_DAMAGE));
+            message_events.send(MessageEvent("You fall through a pit".to_owned()));
+            // land on the same spot below if there is floor, or at the start
+            let below = dungeon_level.0 + 1;
+            match dungeon.levels.get(below) {
+                Some(level_below) => {
+                    let arrival = level_below
+                        .get_tile(position.x, position.z)
+                        .map(|_| position.clone());
+                    level_transition.go(&mut commands, below, arrival);
+                }
+                None => level_transition.go(&mut commands, dungeon_level.0, None),
+            }
+        }
+        Some(Terrain::Damage) => {
+            damage_events.send(DamageEvent(FLOOR_DAMAGE));
+            message_events.send(MessageEvent("The floor burns".to_owned()));
+        }
+        // keep sliding the same way until something stops the player
+        Some(Terrain::Ice) if action.is_step() => motion.queue.push_front(action),
+        _ => (),
+    }
 }
 
 fn get_player_transform(direction: &Direction, x: f32, z: f32) -> Transform {
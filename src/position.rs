@@ -12,21 +12,17 @@ pub struct Position {
 }
 
 impl Position {
-    pub fn go_forward(&mut self) {
-        match self.direction {
+    /// Steps one tile towards `direction` without changing the facing.
+    pub fn go(&mut self, direction: &Direction) {
+        match direction {
             Direction::Right => self.x += 1,
             Direction::Up => self.z -= 1,
             Direction::Left => self.x -= 1,
             Direction::Down => self.z += 1,
         };
     }
-    pub fn go_backward(&mut self) {
-        match self.direction {
-            Direction::Right => self.x -= 1,
-            Direction::Up => self.z += 1,
-            Direction::Left => self.x += 1,
-            Direction::Down => self.z -= 1,
-        };
+    pub fn go_forward(&mut self) {
+        self.go(&self.direction.clone());
     }
     pub fn rotate_right(&mut self) {
         self.direction = match self.direction {
@@ -58,6 +54,13 @@ pub fn get_transform(direction: &Direction, x: f32, z: f32) -> Transform {
         ..default()
     }
 }
+pub const DIRECTIONS: [Direction; 4] = [
+    Direction::Right,
+    Direction::Up,
+    Direction::Left,
+    Direction::Down,
+];
+
 #[derive(PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
 pub enum Direction {
     Right,
@@ -66,6 +69,22 @@ pub enum Direction {
     Down,
 }
 impl Direction {
+    pub fn left(&self) -> Direction {
+        match *self {
+            Direction::Right => Direction::Up,
+            Direction::Up => Direction::Left,
+            Direction::Left => Direction::Down,
+            Direction::Down => Direction::Right,
+        }
+    }
+    pub fn right(&self) -> Direction {
+        match *self {
+            Direction::Right => Direction::Down,
+            Direction::Up => Direction::Right,
+            Direction::Left => Direction::Up,
+            Direction::Down => Direction::Left,
+        }
+    }
     pub fn reverse(&self) -> Direction {
         match *self {
             Direction::Right => Direction::Left,
@@ -92,12 +111,12 @@ impl fmt::Display for Direction {
 impl FromStr for Direction {
     type Err = ();
     fn from_str(input: &str) -> Result<Direction, Self::Err> {
-        return match input.to_lowercase().as_str() {
+        match input.to_lowercase().as_str() {
             "right" => Ok(Direction::Right),
             "up" => Ok(Direction::Up),
             "left" => Ok(Direction::Left),
             "down" => Ok(Direction::Down),
             _ => Err(()),
-        };
+        }
     }
 }
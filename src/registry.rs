@@ -0,0 +1,126 @@
+use crate::dungeon::{Dungeon, DungeonLevel, EntityType, EventEntity};
+use crate::position::{get_transform, Position};
+use crate::world::{EntityIid, WorldState};
+use crate::SpawnDungeonEvent;
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+pub type EntityBuilder = Box<dyn Fn(&mut EntityCommands, &EventEntity) + Send + Sync>;
+
+/// Component builders for the LDtk entity identifiers the game has no type of
+/// its own for. Built-in types are spawned by their own systems.
+#[derive(Resource, Default)]
+pub struct EntityRegistry(HashMap<String, Vec<EntityBuilder>>);
+impl EntityRegistry {
+    /// Panics on a built-in identifier, whose builder would never run.
+    pub fn register(
+        &mut self,
+        identifier: &str,
+        builder: impl Fn(&mut EntityCommands, &EventEntity) + Send + Sync + 'static,
+    ) {
+        assert!(
+            identifier.parse::<EntityType>().is_err(),
+            "{} is a built-in entity type and can't be registered",
+            identifier
+        );
+        self.0
+            .entry(identifier.to_owned())
+            .or_default()
+            .push(Box::new(builder));
+    }
+
+    pub fn contains(&self, identifier: &str) -> bool {
+        self.0.contains_key(identifier)
+    }
+}
+
+pub trait RegisterLdtkEntity {
+    /// Runs `builder` on every spawned LDtk entity with this identifier.
+    fn register_ldtk_entity(
+        &mut self,
+        identifier: &str,
+        builder: impl Fn(&mut EntityCommands, &EventEntity) + Send + Sync + 'static,
+    ) -> &mut Self;
+}
+impl RegisterLdtkEntity for App {
+    fn register_ldtk_entity(
+        &mut self,
+        identifier: &str,
+        builder: impl Fn(&mut EntityCommands, &EventEntity) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.init_resource::<EntityRegistry>();
+        self.world
+            .resource_mut::<EntityRegistry>()
+            .register(identifier, builder);
+        self
+    }
+}
+
+/// Spawns the custom entities of the level with their iid, `Position` and
+/// transform, and lets the registered builders add the rest. Entities nobody
+/// registered are skipped with a warning.
+pub fn spawn_registered_entities(
+    mut commands: Commands,
+    registry: Res<EntityRegistry>,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    world_state: Res<WorldState>,
+    spawn_events: EventReader<SpawnDungeonEvent>,
+) {
+    if spawn_events.is_empty() {
+        return;
+    }
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    for entity in level.entities().iter() {
+        let EntityType::Custom(identifier) = &entity.entity_type else {
+            continue;
+        };
+        let Some(builders) = registry.0.get(identifier) else {
+            warn!("Unknown entity {} at {},{}", identifier, entity.x, entity.z);
+            continue;
+        };
+        if world_state.is_removed(dungeon_level.0, &entity.iid) {
+            continue;
+        }
+        let position = world_state.position(
+            dungeon_level.0,
+            &entity.iid,
+            Position {
+                direction: entity.direction.clone(),
+                x: entity.x,
+                z: entity.z,
+            },
+        );
+        let mut spawned = commands.spawn((
+            EntityIid(entity.iid.clone()),
+            SpatialBundle::from_transform(get_transform(
+                &position.direction,
+                position.x as f32,
+                position.z as f32,
+            )),
+            position,
+        ));
+        for builder in builders {
+            builder(&mut spawned, entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_custom_identifier() {
+        let mut app = App::new();
+        app.register_ldtk_entity("Torch", |_, _| {});
+        assert!(app.world.resource::<EntityRegistry>().contains("Torch"));
+    }
+
+    #[test]
+    #[should_panic(expected = "built-in")]
+    fn register_built_in_identifier() {
+        App::new().register_ldtk_entity("Cat", |_, _| {});
+    }
+}
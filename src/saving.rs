@@ -1,46 +1,295 @@
+use crate::automap::Explored;
+use crate::dialogue::DialogueFlags;
 use crate::dungeon::DungeonLevel;
+use crate::generator::DungeonSeed;
+use crate::input::KeyBindings;
+use crate::inventory::Inventory;
 use crate::position::Position;
-use ::serde_json::{from_str, from_value, json, to_string, Value};
+use crate::stats::PlayerStats;
+use crate::world::WorldState;
+use crate::UiFont;
+use ::serde::{Deserialize, Serialize};
+use ::serde_json::{from_str, from_value, to_string, Value};
+use bevy::prelude::*;
+use std::fmt;
 
-pub fn save_game(player_position: Position, dungeon_level: DungeonLevel) {
-    let json_container = json!({
-        "player_position": player_position,
-        "dungeon_level": dungeon_level,
-    });
+pub const SAVE_VERSION: u64 = 1;
+pub const SAVE_SLOTS: usize = 3;
 
+/// Upgrades a save of version `index` to `index + 1`.
+const MIGRATIONS: [fn(&mut Value); SAVE_VERSION as usize] = [migrate_v0];
+
+/// Version 0 is the untyped blob written before saves had a version. It only
+/// has the dungeon level and player position; everything else starts fresh.
+fn migrate_v0(value: &mut Value) {
+    value["timestamp"] = Value::from(0);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveData {
+    pub version: u64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub dungeon_level: DungeonLevel,
+    pub player_position: Position,
+    #[serde(default)]
+    pub world_state: WorldState,
+    #[serde(default)]
+    pub inventory: Inventory,
+    #[serde(default)]
+    pub dungeon_seed: DungeonSeed,
+    #[serde(default)]
+    pub explored: Explored,
+    #[serde(default)]
+    pub key_bindings: KeyBindings,
+    #[serde(default)]
+    pub dialogue_flags: DialogueFlags,
+    #[serde(default)]
+    pub player_stats: PlayerStats,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    NotFound,
+    Storage(String),
+    Format(serde_json::Error),
+    UnsupportedVersion(u64),
+}
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::NotFound => write!(f, "no save data"),
+            SaveError::Storage(message) => write!(f, "storage error: {}", message),
+            SaveError::Format(error) => write!(f, "broken save data: {}", error),
+            SaveError::UnsupportedVersion(version) => {
+                write!(f, "save version {} is newer than this game", version)
+            }
+        }
+    }
+}
+impl std::error::Error for SaveError {}
+impl From<serde_json::Error> for SaveError {
+    fn from(error: serde_json::Error) -> Self {
+        SaveError::Format(error)
+    }
+}
+
+/// The slot used by the Save and Load buttons.
+#[derive(Resource, Default)]
+pub struct SaveSlot(pub usize);
+
+/// Timestamps of the saves in each slot, `None` for empty or unreadable slots.
+#[derive(Resource)]
+pub struct SaveSlots(pub Vec<Option<u64>>);
+impl Default for SaveSlots {
+    fn default() -> Self {
+        SaveSlots(
+            (0..SAVE_SLOTS)
+                .map(|slot| load_game(slot).ok().map(|data| data.timestamp))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Component)]
+pub struct SlotButton(pub usize);
+
+#[derive(Component)]
+pub struct SlotText(pub usize);
+
+pub fn now() -> u64 {
     #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
-    std::fs::write("save.json", to_string(&json_container).unwrap()).unwrap();
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
 
     #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
-    {
-        let local_storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
-        local_storage
-            .set_item("save_data", &to_string(&json_container).expect("error"))
-            .unwrap();
+    let seconds = (js_sys::Date::now() / 1000.0) as u64;
+
+    seconds
+}
+
+/// Formats a Unix timestamp as "MM-DD hh:mm" (UTC).
+fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let minutes = timestamp % 86400 / 60;
+    // days to civil date, from Howard Hinnant's algorithm
+    let z = days + 719468;
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    format!(
+        "{:02}-{:02} {:02}:{:02}",
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn read_storage(key: &str) -> Result<Option<String>, SaveError> {
+    match std::fs::read_to_string(format!("{}.json", key)) {
+        Ok(json_str) => Ok(Some(json_str)),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(SaveError::Storage(error.to_string())),
     }
 }
 
-pub fn load_game() -> Option<(DungeonLevel, Position)> {
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn write_storage(key: &str, json_str: &str) -> Result<(), SaveError> {
+    std::fs::write(format!("{}.json", key), json_str)
+        .map_err(|error| SaveError::Storage(error.to_string()))
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn local_storage() -> Result<web_sys::Storage, SaveError> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or_else(|| SaveError::Storage("localStorage is not available".to_owned()))
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn read_storage(key: &str) -> Result<Option<String>, SaveError> {
+    local_storage()?
+        .get_item(key)
+        .map_err(|_| SaveError::Storage(format!("failed to read {}", key)))
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn write_storage(key: &str, json_str: &str) -> Result<(), SaveError> {
+    local_storage()?
+        .set_item(key, json_str)
+        .map_err(|_| SaveError::Storage(format!("failed to write {}", key)))
+}
+
+/// `save_0` on native, `save_data_0` in localStorage.
+fn slot_key(slot: usize) -> String {
     #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
-    let json_str = std::fs::read_to_string("save.json").ok();
+    let key = format!("save_{}", slot);
 
     #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
-    let json_str = {
-        let local_storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
-        local_storage.get_item("save_data").unwrap()
+    let key = format!("save_data_{}", slot);
+
+    key
+}
+
+/// Where saves were written before there were slots. Read as slot 0.
+fn legacy_key() -> &'static str {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    let key = "save";
+
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    let key = "save_data";
+
+    key
+}
+
+pub fn save_game(slot: usize, save_data: &SaveData) -> Result<(), SaveError> {
+    write_storage(&slot_key(slot), &to_string(save_data)?)
+}
+
+pub fn load_game(slot: usize) -> Result<SaveData, SaveError> {
+    let json_str = match read_storage(&slot_key(slot))? {
+        Some(json_str) => json_str,
+        None if slot == 0 => read_storage(legacy_key())?.ok_or(SaveError::NotFound)?,
+        None => return Err(SaveError::NotFound),
     };
 
-    match json_str {
-        Some(json_str) => {
-            let mut json_container: Value = from_str(&json_str).unwrap();
+    let mut json_container = Value::Object(from_str(&json_str)?);
+    let version = json_container
+        .get("version")
+        .and_then(|version| version.as_u64())
+        .unwrap_or(0);
+    if version > SAVE_VERSION {
+        return Err(SaveError::UnsupportedVersion(version));
+    }
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        migration(&mut json_container);
+    }
+    json_container["version"] = Value::from(SAVE_VERSION);
+
+    Ok(from_value(json_container)?)
+}
 
-            let dungeon_level: DungeonLevel =
-                from_value(json_container.get_mut("dungeon_level").unwrap().take()).unwrap();
-            let player_position: Position =
-                from_value(json_container.get_mut("player_position").unwrap().take()).unwrap();
+pub fn setup_slot_picker(mut commands: Commands, ui_font: Res<UiFont>) {
+    commands
+        .spawn(NodeBundle {
+            z_index: ZIndex::Global(105),
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(2.0),
+                top: Val::Px(34.0),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for slot in 0..SAVE_SLOTS {
+                parent
+                    .spawn((
+                        SlotButton(slot),
+                        ButtonBundle {
+                            style: Style {
+                                width: Val::Px(72.0),
+                                height: Val::Px(16.0),
+                                align_items: AlignItems::Center,
+                                padding: UiRect::horizontal(Val::Px(2.0)),
+                                margin: UiRect::all(Val::Px(1.0)),
+                                ..default()
+                            },
+                            background_color: crate::NORMAL_BUTTON.into(),
+                            ..default()
+                        },
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            SlotText(slot),
+                            TextBundle::from_section(
+                                "",
+                                TextStyle {
+                                    font: ui_font.0.clone(),
+                                    font_size: 12.0,
+                                    color: Color::WHITE,
+                                },
+                            ),
+                        ));
+                    });
+            }
+        });
+}
 
-            Some((dungeon_level, player_position))
+pub fn interact_slot_button(
+    mut save_slot: ResMut<SaveSlot>,
+    interaction_query: Query<(&Interaction, &SlotButton), Changed<Interaction>>,
+) {
+    for (interaction, slot_button) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            save_slot.0 = slot_button.0;
         }
-        _ => None,
+    }
+}
+
+pub fn update_slot_text(
+    save_slot: Res<SaveSlot>,
+    save_slots: Res<SaveSlots>,
+    mut query: Query<(&mut Text, &SlotText)>,
+) {
+    if !save_slot.is_changed() && !save_slots.is_changed() {
+        return;
+    }
+    for (mut text, slot_text) in &mut query {
+        let marker = if slot_text.0 == save_slot.0 { '>' } else { ' ' };
+        let label = match save_slots.0[slot_text.0] {
+            Some(0) => "Old save".to_owned(),
+            Some(timestamp) => format_timestamp(timestamp),
+            None => "Empty".to_owned(),
+        };
+        text.sections[0].value = format!("{}{} {}", marker, slot_text.0 + 1, label);
     }
 }
@@ -0,0 +1,224 @@
+use crate::combat::Stats;
+use crate::inventory::Inventory;
+use crate::{LoadButton, MessageEvent, ResetButton, UiFont, NORMAL_BUTTON};
+use ::serde::{Deserialize, Serialize};
+use bevy::prelude::*;
+
+const HP_BAR_WIDTH: f32 = 60.0;
+const HP_COLOR: Color = Color::rgb(0.35, 0.75, 0.35);
+const LOW_HP_COLOR: Color = Color::rgb(0.9, 0.25, 0.2);
+
+/// The player's stats and experience. Kept outside of the player entity, which
+/// is respawned on every level change.
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub stats: Stats,
+    pub level: u32,
+    pub xp: u32,
+}
+impl Default for PlayerStats {
+    fn default() -> Self {
+        PlayerStats {
+            stats: Stats::new(20, 4, 1),
+            level: 1,
+            xp: 0,
+        }
+    }
+}
+impl PlayerStats {
+    pub fn is_dead(&self) -> bool {
+        self.stats.hp <= 0
+    }
+
+    /// Experience needed to reach the next level.
+    pub fn next_level_xp(&self) -> u32 {
+        self.level * 20
+    }
+
+    /// Adds experience and returns whether the player levelled up. A new level
+    /// raises the stats and refills HP.
+    pub fn gain_xp(&mut self, xp: u32) -> bool {
+        self.xp += xp;
+        let mut levelled = false;
+        while self.xp >= self.next_level_xp() {
+            self.xp -= self.next_level_xp();
+            self.level += 1;
+            self.stats.max_hp += 5;
+            self.stats.attack += 1;
+            if self.level % 2 == 0 {
+                self.stats.defense += 1;
+            }
+            levelled = true;
+        }
+        if levelled {
+            self.stats.hp = self.stats.max_hp;
+        }
+        levelled
+    }
+}
+
+/// Hurts the player outside of a fight, such as on a damage floor.
+#[derive(Event)]
+pub struct DamageEvent(pub i32);
+
+#[derive(Component)]
+pub struct HudText;
+
+#[derive(Component)]
+pub struct HpBar;
+
+#[derive(Component)]
+pub struct GameOverPanel;
+
+pub fn setup_hud(mut commands: Commands, ui_font: Res<UiFont>) {
+    let text_style = TextStyle {
+        font: ui_font.0.clone(),
+        font_size: 12.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn(NodeBundle {
+            z_index: ZIndex::Global(105),
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(2.0),
+                top: Val::Px(90.0),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((HudText, TextBundle::from_section("", text_style.clone())));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(HP_BAR_WIDTH),
+                        height: Val::Px(4.0),
+                        ..default()
+                    },
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        HpBar,
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Px(HP_BAR_WIDTH),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: HP_COLOR.into(),
+                            ..default()
+                        },
+                    ));
+                });
+        });
+
+    // the buttons are the top row's Load and Reset, handled by the same systems
+    let button_bundle = ButtonBundle {
+        style: Style {
+            width: Val::Px(60.0),
+            height: Val::Px(20.0),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            margin: UiRect::all(Val::Px(2.0)),
+            ..default()
+        },
+        background_color: NORMAL_BUTTON.into(),
+        ..default()
+    };
+    commands
+        .spawn((
+            GameOverPanel,
+            NodeBundle {
+                z_index: ZIndex::Global(140),
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0.2, 0.0, 0.0, 0.85).into(),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Game Over",
+                TextStyle {
+                    font: ui_font.0.clone(),
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                },
+            ));
+            parent.spawn(NodeBundle::default()).with_children(|parent| {
+                parent
+                    .spawn((LoadButton, button_bundle.clone()))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section("Load", text_style.clone()));
+                    });
+                parent
+                    .spawn((ResetButton, button_bundle))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section("Reset", text_style));
+                    });
+            });
+        });
+}
+
+pub fn apply_damage(
+    mut damage_events: EventReader<DamageEvent>,
+    mut player_stats: ResMut<PlayerStats>,
+    mut message_events: EventWriter<MessageEvent>,
+) {
+    for event in damage_events.iter() {
+        if player_stats.is_dead() {
+            continue;
+        }
+        let stats = &mut player_stats.stats;
+        stats.hp = (stats.hp - event.0).max(0);
+        if stats.hp == 0 {
+            message_events.send(MessageEvent("You collapse".to_owned()));
+        }
+    }
+}
+
+pub fn update_hud(
+    player_stats: Res<PlayerStats>,
+    inventory: Res<Inventory>,
+    mut text_query: Query<&mut Text, With<HudText>>,
+    mut bar_query: Query<(&mut Style, &mut BackgroundColor), With<HpBar>>,
+    mut panel_query: Query<&mut Style, (With<GameOverPanel>, Without<HpBar>)>,
+) {
+    if !player_stats.is_changed() && !inventory.is_changed() {
+        return;
+    }
+    let stats = inventory.equipped_stats(&player_stats.stats);
+    text_query.single_mut().sections[0].value = format!(
+        "HP {}/{}\nLv {} XP {}/{}\nATK {} DEF {}",
+        stats.hp,
+        stats.max_hp,
+        player_stats.level,
+        player_stats.xp,
+        player_stats.next_level_xp(),
+        stats.attack,
+        stats.defense
+    );
+
+    let ratio = (stats.hp as f32 / stats.max_hp as f32).clamp(0.0, 1.0);
+    let (mut style, mut color) = bar_query.single_mut();
+    style.width = Val::Px(HP_BAR_WIDTH * ratio);
+    *color = if ratio < 0.3 { LOW_HP_COLOR } else { HP_COLOR }.into();
+
+    panel_query.single_mut().display = if player_stats.is_dead() {
+        Display::Flex
+    } else {
+        Display::None
+    };
+}
@@ -0,0 +1,233 @@
+//! Walking on the player's behalf, one queued move at a time: to a tile
+//! picked on the automap, or towards the nearest unexplored tile.
+
+use crate::automap::{get_visible_tiles, Explored};
+use crate::cat::Cat;
+use crate::combat::Enemy;
+use crate::dungeon::{Dungeon, DungeonLevel, EntityType, Level, Terrain};
+use crate::input::{Action, ActionEvent};
+use crate::player::{Player, PlayerLock, PlayerMotion};
+use crate::position::{Direction, Position, DIRECTIONS};
+use crate::world::WorldState;
+use crate::MessageEvent;
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+/// How far beyond a fork unexplored tiles are still worth stopping for.
+const FORK_DEPTH: usize = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TravelGoal {
+    Tile(i32, i32),
+    Explore,
+}
+
+/// Where the player walks on their own. Any move input cancels it.
+#[derive(Component, Default)]
+pub struct Travel {
+    goal: Option<TravelGoal>,
+    /// The tile the queued step leads to, and how many ways on the tile it left.
+    step: Option<((i32, i32), usize)>,
+    /// Enemies already in sight, `None` until the first move is planned.
+    enemies_in_sight: Option<HashSet<Entity>>,
+}
+impl Travel {
+    pub fn start(&mut self, goal: TravelGoal) {
+        *self = Travel {
+            goal: Some(goal),
+            ..default()
+        };
+    }
+
+    fn stop(&mut self) {
+        *self = Travel::default();
+    }
+}
+
+/// Queues the next move of the travel once the last one is done, and stops on
+/// a message, an enemy coming into sight or, when exploring, a fork.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn update_travel(
+    mut action_events: EventReader<ActionEvent>,
+    dungeon: Res<Dungeon>,
+    dungeon_level: Res<DungeonLevel>,
+    world_state: Res<WorldState>,
+    explored: Res<Explored>,
+    player_lock: PlayerLock,
+    mut query: Query<(&Position, &mut PlayerMotion, &mut Travel), With<Player>>,
+    cat_query: Query<&Position, (With<Cat>, Without<Player>)>,
+    enemy_query: Query<(Entity, &Position), (With<Enemy>, Without<Player>)>,
+    mut message_events: EventWriter<MessageEvent>,
+) {
+    let Ok((position, mut motion, mut travel)) = query.get_single_mut() else {
+        action_events.clear();
+        return;
+    };
+    for action in action_events.iter().map(|event| event.0) {
+        if action == Action::Explore && !player_lock.is_locked() {
+            travel.start(TravelGoal::Explore);
+        } else if action.is_move() {
+            travel.stop();
+        }
+    }
+    let Some(goal) = travel.goal else {
+        return;
+    };
+    if player_lock.is_locked() {
+        travel.stop();
+        return;
+    }
+    if motion.moving || !motion.queue.is_empty() {
+        return;
+    }
+
+    let level = dungeon.levels.get(dungeon_level.0).unwrap();
+    let empty = HashSet::new();
+    let explored = explored.0.get(&dungeon_level.0).unwrap_or(&empty);
+    let mut occupied: HashSet<(i32, i32)> = cat_query.iter().map(|cat| (cat.x, cat.z)).collect();
+    occupied.extend(enemy_query.iter().map(|(_, enemy)| (enemy.x, enemy.z)));
+    // plain floor the player knows of, and stairs only to get off the level
+    let can_step = |from: &Position, direction: &Direction| {
+        let mut next = from.clone();
+        next.go(direction);
+        let cell = (next.x, next.z);
+        (explored.contains(&cell) || goal == TravelGoal::Explore)
+            && !occupied.contains(&cell)
+            && world_state.is_passable(level, dungeon_level.0, from.x, from.z, direction)
+            && world_state.terrain(level, dungeon_level.0, next.x, next.z) == Some(Terrain::Floor)
+            && (goal == TravelGoal::Tile(next.x, next.z)
+                || !level
+                    .entities_at(next.x, next.z)
+                    .any(|entity| entity.destination.is_some()))
+    };
+    let ways = |position: &Position| {
+        DIRECTIONS
+            .iter()
+            .filter(|direction| can_step(position, direction))
+            .count()
+    };
+
+    let visible: HashSet<(i32, i32)> =
+        get_visible_tiles(level, dungeon_level.0, &world_state, position)
+            .into_iter()
+            .collect();
+    let in_sight: HashSet<Entity> = enemy_query
+        .iter()
+        .filter(|(_, enemy)| visible.contains(&(enemy.x, enemy.z)))
+        .map(|(entity, _)| entity)
+        .collect();
+    let spotted = travel
+        .enemies_in_sight
+        .as_ref()
+        .is_some_and(|seen| !in_sight.is_subset(seen));
+    travel.enemies_in_sight = Some(in_sight);
+    if spotted {
+        message_events.send(MessageEvent("An enemy is in sight".to_owned()));
+        travel.stop();
+        return;
+    }
+
+    if let Some((cell, ways_before)) = travel.step.take() {
+        let (x, z) = (position.x, position.z);
+        let on_message = level.entities_at(x, z).any(|entity| {
+            (entity.message.is_some() || entity.dialogue.is_some())
+                && !matches!(entity.entity_type, EntityType::Cat)
+        });
+        // a step that didn't happen, e.g. a cat got in the way
+        if cell != (x, z) || on_message || goal == TravelGoal::Tile(x, z) {
+            travel.stop();
+            return;
+        }
+        // coming out of a corridor where the unexplored parts split up
+        if goal == TravelGoal::Explore
+            && ways_before <= 2
+            && ways(position) > 2
+            && is_fork(level, position, explored, &can_step)
+        {
+            message_events.send(MessageEvent("The way forks".to_owned()));
+            travel.stop();
+            return;
+        }
+    }
+
+    let path = match goal {
+        TravelGoal::Tile(x, z) => level.find_path(
+            position,
+            &Position {
+                direction: position.direction.clone(),
+                x,
+                z,
+            },
+            can_step,
+        ),
+        TravelGoal::Explore => level.find_nearest(
+            position,
+            |tile| !explored.contains(&(tile.x, tile.z)),
+            can_step,
+        ),
+    };
+    let next = match path {
+        Some(path) if !path.is_empty() => path[0].clone(),
+        Some(_) => {
+            travel.stop();
+            return;
+        }
+        None => {
+            message_events.send(MessageEvent(
+                match goal {
+                    TravelGoal::Tile(..) => "You don't know the way there",
+                    TravelGoal::Explore => "Nothing left to explore",
+                }
+                .to_owned(),
+            ));
+            travel.stop();
+            return;
+        }
+    };
+
+    // face the way first, so that the player sees where they are going
+    let facing = position.direction.clone();
+    let action = if next.direction == facing {
+        travel.step = Some(((next.x, next.z), ways(position)));
+        Action::Forward
+    } else if next.direction == facing.left() {
+        Action::TurnLeft
+    } else if next.direction == facing.right() {
+        Action::TurnRight
+    } else {
+        Action::TurnAround
+    };
+    motion.queue.push_back(action);
+}
+
+/// Whether unexplored tiles lie close beyond two or more sides of the tile,
+/// not counting the way the player came.
+fn is_fork(
+    level: &Level,
+    position: &Position,
+    explored: &HashSet<(i32, i32)>,
+    can_step: &impl Fn(&Position, &Direction) -> bool,
+) -> bool {
+    let back = position.direction.reverse();
+    let leads_somewhere = |direction: &&Direction| {
+        let mut next = position.clone();
+        next.go(direction);
+        level
+            .find_nearest(
+                &next,
+                |tile| !explored.contains(&(tile.x, tile.z)),
+                |from, direction| {
+                    let mut to = from.clone();
+                    to.go(direction);
+                    (to.x, to.z) != (position.x, position.z) && can_step(from, direction)
+                },
+            )
+            .is_some_and(|path| path.len() < FORK_DEPTH)
+    };
+    DIRECTIONS
+        .iter()
+        .filter(|direction| **direction != back && can_step(position, direction))
+        .filter(leads_somewhere)
+        .count()
+        >= 2
+}
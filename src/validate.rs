@@ -0,0 +1,244 @@
+use crate::dungeon::{get_external_levels, Dungeon, EntityType, Level, LoadWarning};
+use crate::ldtk::Coordinate as Ldtk;
+use crate::position::{Position, DIRECTIONS};
+use crate::registry::EntityRegistry;
+use crate::CustomEntities;
+use bevy::prelude::*;
+use std::fmt;
+use std::path::Path;
+
+#[derive(PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+/// A map bug at grid cell (`x`, `z`) of a level.
+struct Problem {
+    severity: Severity,
+    level: String,
+    x: i32,
+    z: i32,
+    message: String,
+}
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ({}, {}): {}",
+            match self.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            },
+            self.level,
+            self.x,
+            self.z,
+            self.message
+        )
+    }
+}
+
+/// Checks the project at `args[0]` and prints its problems. Returns the exit
+/// code: 1 when there are errors, 2 when the project can't be read.
+pub fn run(args: &[String]) -> i32 {
+    let [path] = args else {
+        eprintln!("usage: dungeon-crawl validate <file.ldtk>");
+        return 2;
+    };
+    let (dungeon, warnings) = match read_dungeon(Path::new(path)) {
+        Ok(read) => read,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            return 2;
+        }
+    };
+    let mut app = App::new();
+    app.add_plugins(CustomEntities);
+    let registry = app.world.resource::<EntityRegistry>();
+
+    // parts that couldn't be converted come first, in project order
+    let mut problems: Vec<Problem> = warnings
+        .into_iter()
+        .map(|warning| Problem {
+            severity: Severity::Warning,
+            level: warning.level,
+            x: warning.x,
+            z: warning.z,
+            message: warning.message,
+        })
+        .collect();
+    problems.extend(validate(&dungeon, registry));
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .count();
+    println!(
+        "{} error(s), {} warning(s)",
+        errors,
+        problems.len() - errors
+    );
+    if errors > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+fn read_dungeon(path: &Path) -> Result<(Dungeon, Vec<LoadWarning>), Box<dyn std::error::Error>> {
+    let mut ldtk: Ldtk = serde_json::from_slice(&std::fs::read(path)?)?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    for (level, level_path) in get_external_levels(&mut ldtk) {
+        *level = serde_json::from_slice(&std::fs::read(directory.join(level_path))?)?;
+    }
+    Ok(Dungeon::from_ldtk(&ldtk))
+}
+
+fn validate(dungeon: &Dungeon, registry: &EntityRegistry) -> Vec<Problem> {
+    let mut problems = vec![];
+    for (index, level) in dungeon.levels.iter().enumerate() {
+        let mut report = |severity: Severity, x: i32, z: i32, message: String| {
+            problems.push(Problem {
+                severity,
+                level: level.identifier.clone(),
+                x,
+                z,
+                message,
+            })
+        };
+
+        for tile in level.tiles().iter() {
+            for direction in DIRECTIONS.iter() {
+                let mut next = Position {
+                    direction: direction.clone(),
+                    x: tile.x,
+                    z: tile.z,
+                };
+                next.go_forward();
+                let has_wall = tile.has_wall(direction);
+                match level.get_tile(next.x, next.z) {
+                    Some(neighbour) if has_wall && !neighbour.has_wall(&direction.reverse()) => {
+                        report(
+                            Severity::Error,
+                            tile.x,
+                            tile.z,
+                            format!(
+                                "one-sided wall on the {} side, ({}, {}) has no {} wall",
+                                direction,
+                                next.x,
+                                next.z,
+                                direction.reverse()
+                            ),
+                        )
+                    }
+                    None if !has_wall
+                        && !tile.has_door(direction)
+                        && dungeon.get_neighbour(index, &next).is_none() =>
+                    {
+                        report(
+                            Severity::Warning,
+                            tile.x,
+                            tile.z,
+                            format!("open {} side leads off the floor", direction),
+                        )
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        for entity in level.entities().iter() {
+            if level.get_tile(entity.x, entity.z).is_none() {
+                report(
+                    Severity::Error,
+                    entity.x,
+                    entity.z,
+                    format!("{} is on a tile with no floor", entity.iid),
+                );
+            }
+            if let EntityType::Custom(identifier) = &entity.entity_type {
+                if !registry.contains(identifier) {
+                    report(
+                        Severity::Error,
+                        entity.x,
+                        entity.z,
+                        format!("unknown entity identifier {}", identifier),
+                    );
+                }
+            }
+            for (field, value) in entity.fields.iter() {
+                for link in value.links() {
+                    let linked = dungeon.get_linked(&link).is_some()
+                        || dungeon
+                            .levels
+                            .get(link.level)
+                            .and_then(|level| level.get_door_by_iid(&link.iid))
+                            .is_some();
+                    if !linked {
+                        report(
+                            Severity::Warning,
+                            entity.x,
+                            entity.z,
+                            format!("{} links to a missing entity {}", field, link.iid),
+                        );
+                    }
+                }
+            }
+        }
+        for door in level.doors().iter() {
+            if level.get_tile(door.x, door.z).is_none() {
+                report(
+                    Severity::Error,
+                    door.x,
+                    door.z,
+                    format!("door {} is on a tile with no floor", door.iid),
+                );
+            }
+        }
+
+        // with doors open and mechanisms ignored, nothing should be out of reach
+        if let Some(start) = get_player_start(level) {
+            let reached = level.reachable_from(&start);
+            for entity in level.entities().iter() {
+                let on_floor = level.get_tile(entity.x, entity.z).is_some();
+                if on_floor && !reached.contains(&(entity.x, entity.z)) {
+                    report(
+                        Severity::Warning,
+                        entity.x,
+                        entity.z,
+                        format!("{} can't be reached from the PlayerStart", entity.iid),
+                    );
+                }
+            }
+        }
+
+        // only the first level must have one, the others can be entered elsewhere
+        if get_player_start(level).is_none() {
+            report(
+                if index == 0 {
+                    Severity::Error
+                } else {
+                    Severity::Warning
+                },
+                0,
+                0,
+                "no PlayerStart".to_owned(),
+            );
+        }
+    }
+    problems
+}
+
+fn get_player_start(level: &Level) -> Option<Position> {
+    level
+        .entities()
+        .iter()
+        .find(|entity| matches!(entity.entity_type, EntityType::PlayerStart))
+        .map(|entity| Position {
+            direction: entity.direction.clone(),
+            x: entity.x,
+            z: entity.z,
+        })
+}
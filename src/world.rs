@@ -0,0 +1,218 @@
+use crate::door::DoorState;
+use crate::dungeon::{Door, EntityType, EventEntity, Level, Terrain};
+use crate::position::{Direction, Position};
+use ::serde::{Deserialize, Serialize};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// The iid of the level entity a spawned entity was created from.
+#[derive(Component, Clone)]
+pub struct EntityIid(pub String);
+
+/// Changes made during play, keyed by level index. Spawning applies them on
+/// top of the static level data, so they survive level changes and saves.
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
+pub struct WorldState(pub HashMap<usize, LevelState>);
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct LevelState {
+    /// Entities that are gone for good, such as defeated enemies and picked up items.
+    #[serde(default)]
+    pub removed: HashSet<String>,
+    /// Door states changed during play, keyed by door iid.
+    #[serde(default)]
+    pub doors: HashMap<String, DoorState>,
+    /// Where moving entities such as cats were last seen, keyed by iid.
+    #[serde(default)]
+    pub positions: HashMap<String, Position>,
+    /// Mechanisms toggled away from their initial state, by iid.
+    #[serde(default)]
+    pub flags: HashSet<String>,
+    /// Items the player dropped on the level.
+    #[serde(default)]
+    pub dropped: Vec<(i32, i32, String)>,
+}
+
+impl WorldState {
+    pub fn level(&self, level_index: usize) -> Option<&LevelState> {
+        self.0.get(&level_index)
+    }
+
+    pub fn level_mut(&mut self, level_index: usize) -> &mut LevelState {
+        self.0.entry(level_index).or_default()
+    }
+
+    pub fn is_removed(&self, level_index: usize, iid: &str) -> bool {
+        self.level(level_index)
+            .map(|state| state.removed.contains(iid))
+            .unwrap_or(false)
+    }
+
+    pub fn remove(&mut self, level_index: usize, iid: &str) {
+        self.level_mut(level_index).removed.insert(iid.to_owned());
+    }
+
+    /// Where the entity stands now, falling back to its spawn point.
+    pub fn position(&self, level_index: usize, iid: &str, initial: Position) -> Position {
+        self.level(level_index)
+            .and_then(|state| state.positions.get(iid))
+            .cloned()
+            .unwrap_or(initial)
+    }
+
+    /// Whether a switch, plate, wall, bridge or light is on. Walls and lights
+    /// start on unless their `Active` field says otherwise.
+    pub fn is_active(&self, level_index: usize, entity: &EventEntity) -> bool {
+        let initial = entity
+            .field("Active")
+            .and_then(|value| value.as_bool())
+            .unwrap_or(matches!(
+                entity.entity_type,
+                EntityType::Wall | EntityType::Light
+            ));
+        let toggled = self
+            .level(level_index)
+            .map(|state| state.flags.contains(&entity.iid))
+            .unwrap_or(false);
+        initial != toggled
+    }
+
+    pub fn toggle(&mut self, level_index: usize, iid: &str) {
+        let flags = &mut self.level_mut(level_index).flags;
+        if !flags.remove(iid) {
+            flags.insert(iid.to_owned());
+        }
+    }
+
+    /// Whether a raised wall or a missing bridge keeps anyone off the tile.
+    pub fn is_blocked(&self, level: &Level, level_index: usize, x: i32, z: i32) -> bool {
+        level
+            .entities_at(x, z)
+            .any(|entity| match entity.entity_type {
+                EntityType::Wall => self.is_active(level_index, entity),
+                EntityType::Bridge => !self.is_active(level_index, entity),
+                _ => false,
+            })
+    }
+
+    /// The terrain underfoot, where an extended bridge makes water or a pit
+    /// safe to walk on.
+    pub fn terrain(&self, level: &Level, level_index: usize, x: i32, z: i32) -> Option<Terrain> {
+        let terrain = level.get_tile(x, z)?.terrain;
+        let bridged = level.entities_at(x, z).any(|entity| {
+            matches!(entity.entity_type, EntityType::Bridge) && self.is_active(level_index, entity)
+        });
+        Some(if bridged { Terrain::Floor } else { terrain })
+    }
+
+    /// Doors without an entry are in their initial state from the level data.
+    pub fn door_state(&self, level_index: usize, door: &Door) -> DoorState {
+        self.level(level_index)
+            .and_then(|state| state.doors.get(&door.iid))
+            .copied()
+            .unwrap_or(if door.locked {
+                DoorState::Locked
+            } else {
+                DoorState::Closed
+            })
+    }
+
+    pub fn set_door_state(&mut self, level_index: usize, door: &Door, state: DoorState) {
+        self.level_mut(level_index)
+            .doors
+            .insert(door.iid.clone(), state);
+    }
+
+    /// Whether the edge in `direction` can be walked through, as far as doors
+    /// and mechanisms are concerned.
+    pub fn is_passable(
+        &self,
+        level: &Level,
+        level_index: usize,
+        x: i32,
+        z: i32,
+        direction: &Direction,
+    ) -> bool {
+        let mut next = Position {
+            direction: direction.clone(),
+            x,
+            z,
+        };
+        next.go_forward();
+        if self.is_blocked(level, level_index, next.x, next.z) {
+            return false;
+        }
+        match level.get_door(x, z, direction) {
+            Some((_, door)) => self.door_state(level_index, door) == DoorState::Open,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dungeon::Tile;
+
+    /// A one-row level of `terrain` with a retracted bridge on the middle tile.
+    fn bridged(terrain: Terrain) -> Level {
+        let tile = |x: i32, terrain: Terrain| Tile {
+            x,
+            z: 0,
+            walls: HashSet::new(),
+            doors: HashSet::new(),
+            terrain,
+        };
+        let bridge = EventEntity {
+            iid: "bridge".to_owned(),
+            x: 1,
+            z: 0,
+            entity_type: EntityType::Bridge,
+            direction: Direction::Right,
+            message: None,
+            destination: None,
+            behaviour: None,
+            dialogue: None,
+            stats: None,
+            item: None,
+            fields: HashMap::new(),
+        };
+        Level::new(
+            "Test".to_owned(),
+            3,
+            1,
+            vec![
+                tile(0, Terrain::Floor),
+                tile(1, terrain),
+                tile(2, Terrain::Floor),
+            ],
+            vec![bridge],
+            vec![],
+        )
+    }
+
+    #[test]
+    fn bridge_over_water() {
+        let level = bridged(Terrain::Water);
+        let mut world_state = WorldState::default();
+        assert!(world_state.terrain(&level, 0, 1, 0) == Some(Terrain::Water));
+        assert!(!world_state.is_passable(&level, 0, 0, 0, &Direction::Right));
+
+        world_state.toggle(0, "bridge");
+        assert!(world_state.terrain(&level, 0, 1, 0) == Some(Terrain::Floor));
+        assert!(world_state.is_passable(&level, 0, 0, 0, &Direction::Right));
+    }
+
+    #[test]
+    fn bridge_over_pit() {
+        let level = bridged(Terrain::Pit);
+        let mut world_state = WorldState::default();
+        assert!(world_state.terrain(&level, 0, 1, 0) == Some(Terrain::Pit));
+
+        world_state.toggle(0, "bridge");
+        assert!(world_state.terrain(&level, 0, 1, 0) == Some(Terrain::Floor));
+        // other levels keep their own bridge state
+        assert!(world_state.terrain(&level, 1, 1, 0) == Some(Terrain::Pit));
+        assert!(world_state.terrain(&level, 0, 5, 0).is_none());
+    }
+}